@@ -0,0 +1,56 @@
+//! How many frames per second the whole decode pipeline (demuxing, decoding, conversion into RGBA
+//! and going through the engine) gets through, at a few resolutions. There are no sample files in
+//! the repository, so the videos are read from environment variables, and the resolutions whose
+//! variable isn't set are skipped:
+//!
+//! ```sh
+//! for size in 640x360 1280x720 1920x1080; do
+//!     ffmpeg -f lavfi -i testsrc2=size=$size:rate=30 -t 20 -c:v libx264 -pix_fmt yuv420p \
+//!         bench_${size#*x}p.mp4
+//! done
+//! BENCH_VIDEO_360P=bench_360p.mp4 BENCH_VIDEO_720P=bench_720p.mp4 \
+//!     BENCH_VIDEO_1080P=bench_1080p.mp4 cargo bench --features bench
+//! ```
+
+use bevy_ffmpeg::MediaEngine;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::time::Duration;
+
+/// Frames decoded per iteration.
+const FRAMES: usize = 120;
+
+const VIDEOS: [(&str, &str); 3] = [
+    ("360p", "BENCH_VIDEO_360P"),
+    ("720p", "BENCH_VIDEO_720P"),
+    ("1080p", "BENCH_VIDEO_1080P"),
+];
+
+fn decode_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_throughput");
+    group.throughput(Throughput::Elements(FRAMES as u64));
+
+    for (resolution, variable) in VIDEOS {
+        let Ok(path) = std::env::var(variable) else {
+            eprintln!("{variable} isn't set, skipping {resolution}");
+            continue;
+        };
+
+        let mut engine = MediaEngine::new();
+        let id = engine.create_track(&path);
+        engine
+            .get_track_info_blocking(id, Duration::from_secs(10))
+            .unwrap_or_else(|e| panic!("couldn't load {path}: {e}"));
+        // Looping keeps the worker going however many iterations criterion runs.
+        engine.set_loop(id, true);
+        engine.play(id);
+
+        group.bench_function(BenchmarkId::from_parameter(resolution), |b| {
+            b.iter(|| engine.drain(FRAMES))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode_throughput);
+criterion_main!(benches);
@@ -79,6 +79,11 @@ fn video_update_system(
         TrackState::Loading => return,
         TrackState::Ready => {
             let (width, height) = engine.get_size(track_id).unwrap();
+            // High bit depth videos are decoded into 16 bits per component.
+            let texture_format = match engine.get_bit_depth(track_id).unwrap() {
+                bit_depth if bit_depth > 8 => TextureFormat::Rgba16Unorm,
+                _ => TextureFormat::Rgba8UnormSrgb,
+            };
             // We don't need to initialize the image--it will be overridden by a frame message
             // right away anyway.
             let image = Image::new_uninit(
@@ -88,7 +93,7 @@ fn video_update_system(
                     depth_or_array_layers: 1,
                 },
                 TextureDimension::D2,
-                TextureFormat::Rgba8UnormSrgb,
+                texture_format,
                 RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
             );
             let handle = images.add(image);
@@ -119,6 +124,15 @@ fn video_update_system(
         return;
     };
 
+    // The frames jumped to a new position (e.g. the stream has a gap), so we restart our clock
+    // from there.
+    for event in engine.drain_seek_complete_events() {
+        if event.track_id == track_id {
+            video_playback.playback_init_time = current_time;
+            video_playback.playback_init_pts = event.pts;
+        }
+    }
+
     // This loop will traverse the deque of frames and choose the one that is just before our
     // current playback time. All frames that are to the left of the best frame have pts lower than
     // it, so we recycle them along the way. Uploading to GPU is expensive, so we try not to do
@@ -129,7 +143,8 @@ fn video_update_system(
             .unwrap();
     let mut best_frame: Option<VideoFrame> = None;
     while let Some(frame) = engine.peek_video_frame(track_id) {
-        // We don't support invalid pts for now.
+        // The worker makes up a pts for frames that lack one, so the only ones left without are
+        // the first frames of streams without any timestamps, which we have nowhere to put.
         let Some(pts) = frame.pts else {
             let frame = engine.try_get_video_frame(track_id).unwrap();
             engine.reycle_video_frame_buffer(track_id, frame.data);
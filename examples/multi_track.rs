@@ -0,0 +1,164 @@
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use bevy::render::render_resource::*;
+use bevy_ffmpeg::{MediaEngine, TrackId, TrackState, VideoFrame};
+
+/// Number of players we show side by side.
+const NUM_PLAYERS: usize = 3;
+/// Width of each cell in the grid. Videos are scaled to fit in it while keeping their aspect ratio.
+const CELL_WIDTH: f32 = 400.0;
+const CELL_SPACING: f32 = 20.0;
+
+/// Same as in `bevy_media_player`, we read the paths in the main function so that we don't have
+/// to deal with a missing resource in the systems.
+#[derive(Resource)]
+struct VideoPaths(Vec<String>);
+
+#[derive(Resource)]
+struct FfmpegData {
+    media_engine: MediaEngine,
+}
+
+/// Each player owns a single track of the shared engine and the texture its frames are uploaded
+/// to.
+#[derive(Component)]
+struct VideoPlayer {
+    track_id: TrackId,
+    handle: Option<Handle<Image>>,
+    playback_init_time: f64,
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("Please provide at least one path to a video file as an argument");
+        return;
+    }
+
+    // If we are given less paths than players, we just reuse them.
+    let paths = paths.into_iter().cycle().take(NUM_PLAYERS).collect();
+
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_systems(Startup, setup)
+        .add_systems(Update, video_update_system)
+        .insert_resource(VideoPaths(paths))
+        .run();
+}
+
+fn setup(mut commands: Commands, video_paths: Res<VideoPaths>) {
+    commands.spawn(Camera2d::default());
+
+    let mut engine = MediaEngine::new();
+    for (i, path) in video_paths.0.iter().enumerate() {
+        let track_id = engine.create_track(path);
+        let x = (i as f32 - (NUM_PLAYERS - 1) as f32 / 2.0) * (CELL_WIDTH + CELL_SPACING);
+        commands.spawn((
+            VideoPlayer {
+                track_id,
+                handle: None,
+                playback_init_time: 0.0,
+            },
+            Transform::from_xyz(x, 0.0, 0.0),
+        ));
+    }
+
+    info!("Created {} tracks", engine.num_tracks());
+    // Decode a few frames of every track before they start playing so that all of them show up
+    // at roughly the same time.
+    let track_ids: Vec<TrackId> = engine.track_ids().collect();
+    for track_id in track_ids {
+        engine.prefetch(track_id, 5);
+    }
+
+    commands.insert_resource(FfmpegData {
+        media_engine: engine,
+    });
+}
+
+fn video_update_system(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut players: Query<(Entity, &mut VideoPlayer)>,
+    mut ffmpeg_data: ResMut<FfmpegData>,
+) {
+    let current_time = time.elapsed_secs_f64();
+    let engine: &mut MediaEngine = &mut ffmpeg_data.media_engine;
+
+    engine.update();
+
+    for (entity, mut player) in &mut players {
+        let track_id = player.track_id;
+        match engine.get_state(track_id).unwrap() {
+            TrackState::Ready => {
+                let (width, height) = engine.get_size(track_id).unwrap();
+                // High bit depth videos are decoded into 16 bits per component.
+                let texture_format = match engine.get_bit_depth(track_id).unwrap() {
+                    bit_depth if bit_depth > 8 => TextureFormat::Rgba16Unorm,
+                    _ => TextureFormat::Rgba8UnormSrgb,
+                };
+                let image = Image::new_uninit(
+                    Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    texture_format,
+                    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+                );
+                let handle = images.add(image);
+                player.handle = Some(handle.clone());
+                player.playback_init_time = current_time;
+
+                let aspect_ratio = height as f32 / width as f32;
+                commands.entity(entity).insert(Sprite {
+                    image: handle,
+                    custom_size: Some(Vec2::new(CELL_WIDTH, CELL_WIDTH * aspect_ratio)),
+                    ..default()
+                });
+
+                engine.play(track_id);
+            }
+            TrackState::Playing => {
+                // Same frame selection as in `bevy_media_player`, but every player has its own
+                // clock.
+                let playback_time = current_time - player.playback_init_time;
+                let mut best_frame: Option<VideoFrame> = None;
+                while let Some(frame) = engine.peek_video_frame(track_id) {
+                    let Some(pts) = frame.pts else {
+                        let frame = engine.try_get_video_frame(track_id).unwrap();
+                        engine.reycle_video_frame_buffer(track_id, frame.data);
+                        continue;
+                    };
+                    let Some(pts_in_seconds) = engine.pts_in_seconds(track_id, pts) else {
+                        break;
+                    };
+                    if pts_in_seconds > playback_time {
+                        break;
+                    }
+
+                    let frame = engine.try_get_video_frame(track_id).unwrap();
+                    if let Some(old_best_frame) = best_frame.replace(frame) {
+                        engine.reycle_video_frame_buffer(track_id, old_best_frame.data);
+                    }
+                }
+
+                let Some(frame) = best_frame else {
+                    continue;
+                };
+                let Some(handle) = &player.handle else {
+                    continue;
+                };
+                let Some(image) = images.get_mut(handle) else {
+                    continue;
+                };
+                if let Some(old_buffer) = image.data.replace(frame.data) {
+                    engine.reycle_video_frame_buffer(track_id, old_buffer);
+                }
+            }
+            _ => {}
+        }
+    }
+}
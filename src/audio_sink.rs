@@ -0,0 +1,30 @@
+use bevy::log::debug;
+
+/// Somewhere to play the audio of a track, e.g. a CPAL stream or a kira sound. Sinks are called
+/// from the track's worker thread with every chunk of audio right after it is decoded, so `write`
+/// should hand the samples off rather than block on them. See `MediaEngine::add_audio_sink`.
+pub trait AudioSink: Send + Sync {
+    /// `samples` are interleaved, i.e. there are `channels` of them per frame.
+    fn write(&self, samples: &[f32], sample_rate: u32, channels: u16);
+}
+
+/// Throws the audio away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullAudioSink;
+
+impl AudioSink for NullAudioSink {
+    fn write(&self, _samples: &[f32], _sample_rate: u32, _channels: u16) {}
+}
+
+/// Logs how much audio it gets at the debug level, which is handy to check that audio flows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogAudioSink;
+
+impl AudioSink for LogAudioSink {
+    fn write(&self, samples: &[f32], sample_rate: u32, channels: u16) {
+        debug!(
+            "audio sink got {} samples ({sample_rate} Hz, {channels} channels)",
+            samples.len()
+        );
+    }
+}
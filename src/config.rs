@@ -0,0 +1,336 @@
+use super::session::VideoFrame;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A list of files that are played one after the other as if they were a single stream. This uses
+/// ffmpeg's `concat` protocol, which simply glues the files together byte by byte, so it only
+/// works for formats that can be concatenated that way (e.g. MPEG-TS) and all files should share
+/// the same codec parameters.
+#[derive(Debug, Clone)]
+pub struct ConcatSource {
+    pub paths: Vec<String>,
+}
+
+impl ConcatSource {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self { paths }
+    }
+
+    /// Builds the `concat:` url ffmpeg expects, e.g. `concat:file1.ts|file2.ts`.
+    pub fn to_url(&self) -> String {
+        format!("concat:{}", self.paths.join("|"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TrackSource {
+    File(String),
+    Concat(ConcatSource),
+}
+
+impl TrackSource {
+    /// The string that is handed over to ffmpeg to open the source.
+    pub fn url(&self) -> String {
+        match self {
+            TrackSource::File(path) => path.clone(),
+            TrackSource::Concat(concat) => concat.to_url(),
+        }
+    }
+}
+
+impl From<&str> for TrackSource {
+    fn from(path: &str) -> Self {
+        TrackSource::File(path.to_string())
+    }
+}
+
+impl From<String> for TrackSource {
+    fn from(path: String) -> Self {
+        TrackSource::File(path)
+    }
+}
+
+impl From<ConcatSource> for TrackSource {
+    fn from(concat: ConcatSource) -> Self {
+        TrackSource::Concat(concat)
+    }
+}
+
+/// What to do when a packet fails to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeErrorPolicy {
+    /// Stop playback and put the track in an error state on the first error.
+    #[default]
+    Stop,
+    /// Ignore decode errors and carry on with the next packet.
+    Skip,
+    /// Ignore up to the given number of consecutive errors, then stop like `Stop` does. This is
+    /// usually what you want for network streams, where the odd corrupt packet is expected.
+    SkipUpTo(u32),
+}
+
+impl DecodeErrorPolicy {
+    /// Whether `consecutive_errors` errors in a row are still fine.
+    pub fn tolerates(&self, consecutive_errors: u32) -> bool {
+        match self {
+            DecodeErrorPolicy::Stop => false,
+            DecodeErrorPolicy::Skip => true,
+            DecodeErrorPolicy::SkipUpTo(max_errors) => consecutive_errors <= *max_errors,
+        }
+    }
+}
+
+/// Called by a track's worker with every frame it decodes, see `TrackConfig::with_frame_callback`.
+#[derive(Clone)]
+pub struct FrameCallback(Arc<dyn Fn(&VideoFrame) + Send + Sync>);
+
+impl FrameCallback {
+    pub fn new(callback: impl Fn(&VideoFrame) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub fn call(&self, frame: &VideoFrame) {
+        (self.0)(frame)
+    }
+}
+
+impl fmt::Debug for FrameCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FrameCallback").finish_non_exhaustive()
+    }
+}
+
+/// Everything needed to create a track. `MediaEngine::create_track` is a shorthand for creating a
+/// track out of a file with the default configuration.
+#[derive(Debug, Clone)]
+pub struct TrackConfig {
+    pub source: TrackSource,
+    /// Options passed to ffmpeg when the source is opened, e.g. `rtsp_transport=tcp`.
+    pub format_options: HashMap<String, String>,
+    pub decode_error_policy: DecodeErrorPolicy,
+    /// How often the worker reports its position, even while paused. Defaults to 500ms.
+    pub position_update_interval: Duration,
+    /// How long, in microseconds, the worker sleeps between two looks at its commands while it
+    /// isn't decoding, e.g. while paused. Defaults to 1000, see `with_worker_idle_sleep_us`.
+    pub worker_idle_sleep_us: u64,
+    /// Caps how much memory decoded frames that haven't been recycled yet may take. Once the cap is
+    /// reached, the worker waits for the consumer to recycle a frame instead of dropping any. This
+    /// can only lower the default of 10 frames, and never below what the decoder needs to get its
+    /// frames out, which depends on the codec and the number of threads.
+    pub max_queue_memory_bytes: Option<usize>,
+    /// Ignores the video stream even if there is one, e.g. the cover art of a music file.
+    pub audio_only: bool,
+    /// Number of threads the video decoder may use, 1 by default. More threads help with very high
+    /// resolution or bitrate content (e.g. 8K), at the cost of a frame of latency per thread. 0
+    /// lets ffmpeg pick based on the number of cores. The frame pool grows by a buffer per thread
+    /// if needed.
+    ///
+    /// This is ffmpeg's own frame threading (slice threading in low latency mode) within the
+    /// track's single worker, rather than several workers each decoding part of the packets: the
+    /// decoder already hands frames out in order, and a worker can't decode the packets of a group
+    /// of pictures without the others' reference frames anyway.
+    pub decoder_threads: usize,
+    /// Whether 8-bit videos are converted into `BGRA` instead of `RGBA`. See
+    /// `with_pixel_format_probe`.
+    pub bgra_output: bool,
+    /// Tunes demuxing and decoding for live sources (RTSP cameras, capture cards...), see
+    /// `with_low_latency_mode`.
+    pub low_latency: bool,
+    /// How many times we try to reopen a live stream (RTSP...) once it drops, waiting `2^attempt`
+    /// seconds before each attempt. `None`, the default, ends the track like any other stream.
+    pub max_reconnect_attempts: Option<u32>,
+    /// Options of the audio resampler (libswresample), see `with_swr_options`.
+    pub swr_options: HashMap<String, String>,
+    /// Called by the worker with every frame before it is queued, see `with_frame_callback`.
+    pub frame_callback: Option<FrameCallback>,
+    /// Whether we seek to the first frame right after opening media that doesn't start at 0, see
+    /// `with_seek_before_play`. Defaults to `true`.
+    pub seek_to_start: bool,
+    /// The rows of the frames are padded to a multiple of this many bytes, see
+    /// `with_output_stride_alignment`. Defaults to 1, i.e. no padding.
+    pub stride_alignment: usize,
+    /// Whether every frame also comes at half its resolution, see `with_subsampled_output`.
+    pub subsampled_output: bool,
+}
+
+impl TrackConfig {
+    pub fn new(source: impl Into<TrackSource>) -> Self {
+        Self {
+            source: source.into(),
+            format_options: HashMap::new(),
+            decode_error_policy: DecodeErrorPolicy::default(),
+            position_update_interval: Duration::from_millis(500),
+            worker_idle_sleep_us: 1000,
+            max_queue_memory_bytes: None,
+            audio_only: false,
+            decoder_threads: 1,
+            bgra_output: false,
+            low_latency: false,
+            max_reconnect_attempts: None,
+            swr_options: HashMap::new(),
+            frame_callback: None,
+            seek_to_start: true,
+            stride_alignment: 1,
+            subsampled_output: false,
+        }
+    }
+
+    pub fn with_format_option(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.format_options.insert(key.into(), value.into());
+        self
+    }
+
+    /// Sets options of the audio resampler, which converts decoded audio into packed `f32`. Its
+    /// defaults are fine for playback, but it can do better, e.g.
+    /// `[("resampler", "soxr"), ("precision", "33"), ("cheby", "1")]` for the highest quality
+    /// (soxr needs ffmpeg to be built with libsoxr). See ffmpeg's resampler documentation for the
+    /// full list.
+    pub fn with_swr_options<K, V>(mut self, options: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.swr_options.extend(
+            options
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
+
+    /// Same as `with_swr_options`, for a single option, like `with_format_option`.
+    pub fn with_swr_option(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.with_swr_options([(key, value)])
+    }
+
+    pub fn with_decode_error_policy(mut self, policy: DecodeErrorPolicy) -> Self {
+        self.decode_error_policy = policy;
+        self
+    }
+
+    pub fn with_position_update_interval(mut self, interval: Duration) -> Self {
+        self.position_update_interval = interval;
+        self
+    }
+
+    /// A shorter sleep makes a paused track react to commands (play, seek...) sooner, at the cost
+    /// of waking the worker up more often. The default of 1ms is well below a frame at any frame
+    /// rate.
+    pub fn with_worker_idle_sleep_us(mut self, micros: u64) -> Self {
+        self.worker_idle_sleep_us = micros;
+        self
+    }
+
+    pub fn with_max_queue_memory_bytes(mut self, bytes: usize) -> Self {
+        self.max_queue_memory_bytes = Some(bytes);
+        self
+    }
+
+    pub fn with_audio_only(mut self, audio_only: bool) -> Self {
+        self.audio_only = audio_only;
+        self
+    }
+
+    pub fn with_decoder_threads(mut self, threads: usize) -> Self {
+        self.decoder_threads = threads;
+        self
+    }
+
+    /// Picks the pixel format the platform's GPU backend prefers for 8-bit videos: `BGRA` on macOS
+    /// and iOS (Metal) and on Windows (DX12), `RGBA` everywhere else. Frames can then be uploaded
+    /// as is, without the GPU having to swizzle them. Remember to create the texture with the
+    /// matching format, i.e. check `VideoFrame::pixel_format`.
+    pub fn with_pixel_format_probe(mut self) -> Self {
+        self.bgra_output = cfg!(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "windows"
+        ));
+        self
+    }
+
+    /// Shows frames as soon as they arrive, for live sources. The demuxer stops buffering
+    /// (`fflags=nobuffer`) and barely probes the input before starting (`probesize`,
+    /// `analyzeduration`), and the decoder outputs frames without reordering them (`LOW_DELAY`)
+    /// and doesn't use frame threading. Format options set explicitly take precedence.
+    ///
+    /// The catch is that there is less to go on. Streams with B-frames may come out with decode
+    /// errors or glitches from skipped reference frames, so pair this with a lenient
+    /// `DecodeErrorPolicy`, and the stream parameters may be guessed wrong with such a short probe.
+    /// Don't use this for files.
+    pub fn with_low_latency_mode(mut self, low_latency: bool) -> Self {
+        self.low_latency = low_latency;
+        self
+    }
+
+    pub fn with_max_reconnect_attempts(mut self, attempts: u32) -> Self {
+        self.max_reconnect_attempts = Some(attempts);
+        self
+    }
+
+    /// Some files, typically recordings of broadcasts (DVB, MPEG-TS), declare a start time far
+    /// from 0, and their first packets may be from before the first frame that can be decoded. We
+    /// seek to the first frame right after opening them so that playback starts exactly there.
+    /// This costs a seek when opening such files, so it can be turned off.
+    pub fn with_seek_before_play(mut self, seek_to_start: bool) -> Self {
+        self.seek_to_start = seek_to_start;
+        self
+    }
+
+    /// Pads the rows of the frames so that each one starts on a multiple of `alignment` bytes, e.g.
+    /// 256 for the `bytes_per_row` of texture copies in wgpu (and so Vulkan, Metal and DX12).
+    /// Frames can then be copied straight into a texture. `VideoFrame::stride` is the size of a
+    /// padded row, and the buffers of the pool are that much bigger.
+    pub fn with_output_stride_alignment(mut self, alignment: usize) -> Self {
+        self.stride_alignment = alignment;
+        self
+    }
+
+    /// Also converts every frame into a second one at half its width and height, e.g. for a
+    /// picture-in-picture or a blurred background. These are read with
+    /// `MediaEngine::try_get_subsampled_video_frame`. Their buffers don't come from the frame
+    /// pool, so this allocates a little for every frame.
+    pub fn with_subsampled_output(mut self, enabled: bool) -> Self {
+        self.subsampled_output = enabled;
+        self
+    }
+
+    /// Calls `callback` on the worker thread with every decoded frame, right after it is converted
+    /// and before it is queued, e.g. to run inference on it without going through the engine. The
+    /// worker waits for the callback, so a slow one holds up decoding (but not the app).
+    pub fn with_frame_callback(
+        mut self,
+        callback: impl Fn(&VideoFrame) + Send + Sync + 'static,
+    ) -> Self {
+        self.frame_callback = Some(FrameCallback::new(callback));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_tolerates_nothing() {
+        assert!(!DecodeErrorPolicy::Stop.tolerates(0));
+        assert!(!DecodeErrorPolicy::Stop.tolerates(1));
+    }
+
+    #[test]
+    fn skip_tolerates_everything() {
+        assert!(DecodeErrorPolicy::Skip.tolerates(1));
+        assert!(DecodeErrorPolicy::Skip.tolerates(u32::MAX));
+    }
+
+    #[test]
+    fn skip_up_to_tolerates_up_to_its_limit() {
+        let policy = DecodeErrorPolicy::SkipUpTo(3);
+        assert!(policy.tolerates(1));
+        assert!(policy.tolerates(3));
+        assert!(!policy.tolerates(4));
+        assert!(!DecodeErrorPolicy::SkipUpTo(0).tolerates(1));
+    }
+}
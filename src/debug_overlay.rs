@@ -0,0 +1,85 @@
+use super::session::{VideoFrame, bytes_per_pixel};
+
+/// Size of a glyph of the overlay font, in font pixels.
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+/// Every font pixel is drawn as a `SCALE` x `SCALE` square, so that the text stays readable on
+/// large videos.
+const SCALE: usize = 2;
+
+/// Rows of the glyph of `c`, top to bottom, the most significant of the 3 bits being the leftmost
+/// pixel. The font only has what the overlay needs: digits, a few uppercase letters and some
+/// punctuation. Anything else is blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' | 'O' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' | 'S' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Draws `text` in white on a black box in the top left corner of `frame`. Whatever doesn't fit in
+/// the frame is cut off.
+pub fn draw_text(frame: &mut VideoFrame, text: &str) {
+    // One font pixel of margin around the text, and between glyphs.
+    let box_width = (text.chars().count() * (GLYPH_WIDTH + 1) + 1) * SCALE;
+    let box_height = (GLYPH_HEIGHT + 2) * SCALE;
+    for y in 0..box_height.min(frame.height as usize) {
+        for x in 0..box_width.min(frame.width as usize) {
+            put_pixel(frame, x, y, false);
+        }
+    }
+
+    for (i, c) in text.chars().enumerate() {
+        let left = (i * (GLYPH_WIDTH + 1) + 1) * SCALE;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let x = left + column * SCALE + dx;
+                        let y = (row + 1) * SCALE + dy;
+                        if x < frame.width as usize && y < frame.height as usize {
+                            put_pixel(frame, x, y, true);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sets a pixel to opaque white or black. Every format we output has its alpha last, so this works
+/// the same for `RGBA`, `BGRA` and `RGBA64LE`.
+fn put_pixel(frame: &mut VideoFrame, x: usize, y: usize, white: bool) {
+    let bytes_per_pixel = bytes_per_pixel(frame.pixel_format) as usize;
+    let offset = y * frame.stride as usize + x * bytes_per_pixel;
+    let Some(pixel) = frame.data.get_mut(offset..offset + bytes_per_pixel) else {
+        return;
+    };
+    let alpha = bytes_per_pixel / 4 * 3;
+    pixel[..alpha].fill(if white { 0xff } else { 0 });
+    pixel[alpha..].fill(0xff);
+}
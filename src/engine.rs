@@ -1,38 +1,446 @@
+use super::audio_sink::AudioSink;
+use super::config::{TrackConfig, TrackSource};
+use super::debug_overlay::draw_text;
+use super::error::MediaError;
 use super::frame_pool::FramePool;
-use super::session::VideoFrame;
+use super::probe::{ProbeCache, TrackInfo, probe_media};
+use super::session::{
+    AudioFrame, ColorMatrix, SeekMode, StreamDescriptor, SubtitleFrame, VideoFrame, remux_segment,
+};
+use super::stats::PlaybackStats;
 use super::worker::{WorkerCommand, WorkerHandle, WorkerMessage, spawn_worker_thread};
+use bevy::ecs::resource::Resource;
 use ffmpeg::rescale::Rescale;
 use ffmpeg_next as ffmpeg;
+use std::cmp::Reverse;
 use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+#[cfg(feature = "thread_safe")]
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
+/// How many audio frames are kept around for a track before the oldest ones are dropped. This is a
+/// few seconds of audio for most codecs.
+const MAX_QUEUED_AUDIO_FRAMES: usize = 256;
+
+/// How many half resolution frames are kept around for a track before the oldest ones are dropped,
+/// see `TrackConfig::with_subsampled_output`. Unlike full frames, nothing else limits them.
+const MAX_QUEUED_SUBSAMPLED_FRAMES: usize = 16;
+
+/// How far behind the rest of its group a track may fall, in seconds, before `update_group` seeks
+/// it forward. Seeking costs more than a frame, so there is no point in being stricter.
+const GROUP_SYNC_TOLERANCE: f64 = 0.05;
+
+/// How many warnings are kept around for a track before the oldest ones are dropped.
+const MAX_WARNINGS: usize = 32;
+
+/// How long `drain` waits for new frames before deciding the workers have nothing more to give.
+const DRAIN_IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Owns every track and talks to their workers. Decoding happens on the workers' threads, and
+/// everything else happens on whichever thread calls into the engine, most methods only touching
+/// the state the last `update` left behind.
+///
+/// The engine is `Send` and `Sync`, as Bevy requires of resources: the only thing it shares with
+/// the workers are crossbeam channels, which can be used from any thread. Everything that changes
+/// anything takes `&mut self` though, so sharing an engine outside of Bevy means putting it behind
+/// a lock, which is what `SharedMediaEngine` does with the `thread_safe` feature. Within Bevy,
+/// systems that only read from it through `Res<MediaEngine>` can run in parallel.
+#[derive(Resource)]
 pub struct MediaEngine {
     next_id: u32,
     tracks: HashMap<TrackId, MediaTrack>,
+    probe_cache: Option<ProbeCache>,
+    seek_complete_events: Vec<TrackSeekCompleteEvent>,
+    next_group_id: u32,
+    groups: HashMap<TrackGroupId, Vec<TrackId>>,
 }
 
-#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(bevy::reflect::Reflect))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackId(u32);
 
+/// A set of tracks that play in sync, e.g. the angles of a multi-angle video. See
+/// `MediaEngine::create_track_group`.
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
+pub struct TrackGroupId(u32);
+
+/// Emitted when a track's frames jump to a new position, either because of a seek or because of a
+/// pts discontinuity in the stream. Seeks are always reported, however short, while jumps within
+/// the stream are only reported past `MediaEngine::set_max_pts_jump_threshold`. Anything that keeps
+/// a playback clock based on the frames' pts should restart it from `pts`, which is the pts of the
+/// first frame at the new position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackSeekCompleteEvent {
+    pub track_id: TrackId,
+    pub pts: i64,
+}
+
 #[derive(Eq, PartialEq, Clone, Debug)]
+#[cfg_attr(feature = "bevy_reflect", derive(bevy::reflect::Reflect))]
 pub enum TrackState {
     Loading,
     Ready,
     Playing,
     Paused,
     Ended,
+    /// A live stream dropped and the worker is trying to reopen it, see
+    /// `TrackConfig::max_reconnect_attempts`. The track becomes `Ready` again if it succeeds.
+    Reconnecting {
+        attempt: u32,
+    },
     Error(String),
 }
 
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum LoopMode {
+    /// The track ends once it reaches the end of the stream.
+    None,
+    /// The track goes back to the start the given number of times, so it is played `n + 1` times
+    /// in total.
+    Count(u32),
+    Infinite,
+    /// The track plays forwards, then backwards once it reaches the end, then forwards again once
+    /// it reaches the start, and so on. Keep in mind that playing backwards is much more expensive
+    /// than playing forwards.
+    PingPong,
+}
+
+/// Keeps the pts of a track going forwards when the stream resets its timestamps, which live
+/// streams tend to do when they reconnect. Without this, the playback clock would jump backwards.
+#[derive(Debug, Clone)]
+pub struct PtsMapper {
+    offset: i64,
+    last_pts: Option<i64>,
+    threshold: i64,
+}
+
+impl PtsMapper {
+    /// `threshold` is how far back, in the stream's time base, the pts has to jump for it to count
+    /// as a reset. Smaller jumps are left alone, since reordered frames go back a little.
+    pub fn new(threshold: i64) -> Self {
+        Self {
+            offset: 0,
+            last_pts: None,
+            threshold,
+        }
+    }
+
+    pub fn map(&mut self, pts: i64) -> i64 {
+        let mut mapped = pts + self.offset;
+        if let Some(last_pts) = self.last_pts
+            && mapped < last_pts - self.threshold
+        {
+            // We have no idea how long the stream was gone for, so we carry on right after the
+            // last frame.
+            self.offset += last_pts + 1 - mapped;
+            mapped = last_pts + 1;
+        }
+        self.last_pts = Some(mapped);
+        mapped
+    }
+
+    /// What is currently added to the pts of the stream.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    /// Forgets about the timestamps seen so far. Seeking backwards is a jump we do want.
+    pub fn reset(&mut self) {
+        self.offset = 0;
+        self.last_pts = None;
+    }
+}
+
+/// Converts between the timestamps of a stream and seconds relative to its first frame, which is
+/// how positions are expressed everywhere in the engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtsToWallClock {
+    time_base: ffmpeg::Rational,
+    start_pts: i64,
+}
+
+impl PtsToWallClock {
+    pub fn new(time_base: ffmpeg::Rational, start_pts: i64) -> Self {
+        Self {
+            time_base,
+            start_pts,
+        }
+    }
+
+    pub fn to_secs(&self, pts: i64) -> f64 {
+        self.duration_to_secs(pts - self.start_pts)
+    }
+
+    pub fn from_secs(&self, secs: f64) -> i64 {
+        self.start_pts + self.duration_from_secs(secs)
+    }
+
+    /// Same as `to_secs`, but for a difference between two timestamps.
+    pub fn duration_to_secs(&self, duration: i64) -> f64 {
+        let microseconds = duration.rescale(self.time_base, ffmpeg::rescale::TIME_BASE);
+        microseconds as f64 / 1_000_000.0
+    }
+
+    /// Same as `from_secs`, but for a difference between two timestamps.
+    pub fn duration_from_secs(&self, secs: f64) -> i64 {
+        ((secs * 1_000_000.0) as i64).rescale(ffmpeg::rescale::TIME_BASE, self.time_base)
+    }
+}
+
+/// Measures how long a track has been playing, pauses excluded, which is what its frames' pts
+/// should be compared against to know when to show them.
+#[derive(Debug, Clone, Default)]
+struct PlaybackClock {
+    /// When the clock was last started, if it is running.
+    started_at: Option<Instant>,
+    /// What the clock read when it was last started, paused or set.
+    base_secs: f64,
+}
+
+impl PlaybackClock {
+    fn start(&mut self) {
+        self.started_at.get_or_insert_with(Instant::now);
+    }
+
+    fn pause(&mut self) {
+        self.base_secs = self.elapsed_secs();
+        self.started_at = None;
+    }
+
+    /// Moves the clock to `secs`, without starting or stopping it.
+    fn set(&mut self, secs: f64) {
+        self.base_secs = secs;
+        if self.started_at.is_some() {
+            self.started_at = Some(Instant::now());
+        }
+    }
+
+    fn elapsed_secs(&self) -> f64 {
+        let running = self
+            .started_at
+            .map_or(0.0, |started_at| started_at.elapsed().as_secs_f64());
+        self.base_secs + running
+    }
+}
+
+/// Draining iterator over the queued frames of a track, oldest first. See
+/// `MediaEngine::iter_video_frames`.
+pub struct VideoFrameIter<'a> {
+    queue: &'a mut VecDeque<VideoFrame>,
+}
+
+impl Iterator for VideoFrameIter<'_> {
+    type Item = VideoFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.pop_back()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.queue.len(), Some(self.queue.len()))
+    }
+}
+
+/// The next frame of a track, still in its queue. See `MediaEngine::current_video_frame_ref`.
+pub struct VideoFrameRef<'a> {
+    queue: &'a mut VecDeque<VideoFrame>,
+    pool: Option<&'a FramePool>,
+}
+
+impl VideoFrameRef<'_> {
+    fn frame(&self) -> &VideoFrame {
+        // We only hand out a `VideoFrameRef` if there is a frame, and only `recycle` pops it.
+        self.queue.back().unwrap()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.frame().data
+    }
+
+    pub fn pts(&self) -> Option<i64> {
+        self.frame().pts
+    }
+
+    pub fn width(&self) -> u32 {
+        self.frame().width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.frame().height
+    }
+
+    /// Pops the frame and gives its buffer back to the frame pool, for when we are done with it.
+    pub fn recycle(self) {
+        let frame = self.queue.pop_back().unwrap();
+        if let Some(pool) = self.pool {
+            pool.recycle(frame.data).ok();
+        }
+    }
+}
+
 struct MediaTrack {
     desired_state: TrackState,
     worker_state: TrackState,
     worker: WorkerHandle,
-    loop_enabled: bool,
-    time_base: Option<ffmpeg::Rational>,
-    start_pts: Option<i64>,
+    loop_mode: LoopMode,
+    loops_done: u32,
+    /// Whether the track pauses on its last frame instead of ending, when it doesn't loop.
+    pause_on_end: bool,
+    /// Start and end, in seconds, of the part of the track that is played, see
+    /// `MediaEngine::set_playback_region`.
+    playback_region: Option<(f64, f64)>,
+    /// Set once a frame went past the end of the playback region, until the seek back to its
+    /// start completes, so that the frames still on their way don't loop the track again.
+    region_end_reached: bool,
+    forward: bool,
+    decode_interval: Option<Duration>,
+    /// Timeline of the stream that drives playback (video, or audio without video), known once
+    /// the worker has opened the source.
+    timeline: Option<PtsToWallClock>,
+    current_pts: Option<i64>,
+    /// Where the worker's demuxer is in the input, in bytes, as of the last position update.
+    byte_position: Option<u64>,
+    /// Pts of the last frame that was queued, as the stream has it rather than as `pts_mapper`
+    /// makes it, used to detect discontinuities.
+    last_queued_pts: Option<i64>,
+    /// Set once the worker reports a seek, until the first frame at the new position is queued.
+    seeked: bool,
+    /// Two consecutive frames further apart than this, in seconds, count as a discontinuity.
+    max_pts_jump_threshold: f64,
+    /// Set up once we know the time base of the video.
+    pts_mapper: Option<PtsMapper>,
     frame_pool: Option<FramePool>,
     size: Option<(u32, u32)>,
+    bit_depth: Option<u8>,
+    streams: Vec<StreamDescriptor>,
+    attached_picture: Option<Vec<u8>>,
+    info: Option<TrackInfo>,
     video_queue: VecDeque<VideoFrame>,
+    /// Half resolution frames, see `TrackConfig::with_subsampled_output`.
+    subsampled_queue: VecDeque<VideoFrame>,
+    /// Called with every frame before it is queued, see `MediaEngine::register_video_frame_hook`.
+    frame_hooks: Vec<Box<dyn Fn(&VideoFrame) + Send + Sync>>,
+    /// Whether frames get the pts, their index and the pool's state drawn onto them, see
+    /// `MediaEngine::set_debug_overlay`.
+    debug_overlay: bool,
+    /// Number of frames queued since the track was (re)loaded.
+    frames_queued: u64,
+    /// Whether the last frame is handed out again when the queue runs dry during playback.
+    duplicate_on_stall: bool,
+    /// Copy of the last frame handed out, kept around while `duplicate_on_stall` is set.
+    last_frame: Option<VideoFrame>,
+    /// Whether the track was created with `TrackConfig::audio_only`.
+    audio_only: bool,
+    /// Sample rate and number of channels of the audio frames.
+    audio_format: Option<(u32, u16)>,
+    audio_queue: VecDeque<AudioFrame>,
+    /// RMS of the last audio frame received from the worker, see `extract_audio_rms`.
+    last_audio_rms: Option<f32>,
+    /// Subtitles in the order they were decoded. Unlike frames, they are not consumed but looked up
+    /// by time, and dropped once they are over.
+    subtitle_queue: VecDeque<SubtitleFrame>,
+    /// Shift applied to subtitles when they are looked up, in seconds, see `set_subtitle_delay`.
+    subtitle_delay: f64,
+    /// The last `MAX_WARNINGS` warnings of the worker, oldest first.
+    warnings: VecDeque<String>,
+    clock: PlaybackClock,
+    stats: PlaybackStats,
+}
+
+impl MediaTrack {
+    /// Sends `cmd` to the worker. This can only fail if the worker is gone, in which case the track
+    /// ends up in an error state.
+    fn send_command(&mut self, cmd: WorkerCommand) {
+        if let Err(e) = self.worker.send_command(cmd) {
+            self.worker_state = TrackState::Error(e.to_string());
+        }
+    }
+
+    /// Sorts the queued frames in playback order: by increasing pts when playing forwards (the
+    /// queue is consumed from the back), and the other way around when playing backwards.
+    fn sort_video_queue(&mut self) {
+        let frames = self.video_queue.make_contiguous();
+        if self.forward {
+            frames.sort_by_key(|frame| Reverse(frame.pts));
+        } else {
+            frames.sort_by_key(|frame| frame.pts);
+        }
+    }
+
+    /// Converts `pts` into seconds relative to the first frame, see `MediaEngine::pts_in_seconds`.
+    fn pts_in_seconds(&self, pts: i64) -> Option<f64> {
+        Some(self.timeline?.to_secs(pts))
+    }
+
+    /// Goes back to the start of the track (or of its playback region) or ends it, depending on
+    /// the loop mode. This is what happens once the worker reaches the end of the stream, or once
+    /// frames go past the end of the playback region.
+    fn reach_end(&mut self) {
+        let restart = match self.loop_mode {
+            LoopMode::None => false,
+            LoopMode::Count(count) => self.loops_done < count,
+            LoopMode::Infinite | LoopMode::PingPong => true,
+        };
+
+        if !restart {
+            if self.pause_on_end {
+                // The worker may still have something to decode, e.g. past the playback region.
+                self.send_command(WorkerCommand::Pause);
+                self.desired_state = TrackState::Paused;
+                self.worker_state = TrackState::Paused;
+            } else {
+                self.worker_state = TrackState::Ended;
+            }
+            self.clock.pause();
+            return;
+        }
+
+        self.loops_done += 1;
+        if self.loop_mode == LoopMode::PingPong {
+            // When we turn around at the end, the worker simply continues backwards from the last
+            // frame it decoded, so there is nothing to seek to.
+            self.forward = !self.forward;
+            self.send_command(WorkerCommand::SetDirection {
+                forward: self.forward,
+            });
+        }
+        if self.forward {
+            let start = self.playback_region.map_or(0.0, |(start, _)| start);
+            self.send_command(WorkerCommand::Seek {
+                seconds: start,
+                mode: SeekMode::Precise,
+            });
+            self.clock.set(start);
+        }
+        // The worker stops playing once it reaches the end of the stream, so we have to kick it
+        // again.
+        self.send_command(WorkerCommand::Play);
+        self.worker_state = TrackState::Playing;
+    }
+
+    /// Takes the position the worker reports, in the stream's own timestamps, into account.
+    fn set_current_pts(&mut self, pts: i64) {
+        let offset = self.pts_mapper.as_ref().map_or(0, PtsMapper::offset);
+        let pts = pts + offset;
+        self.current_pts = Some(pts);
+        // Going backwards, the subtitles we are past are the ones coming up next.
+        if self.forward {
+            let delay = self.subtitle_delay_pts();
+            self.subtitle_queue
+                .retain(|subtitle| subtitle.end_pts + delay > pts);
+        }
+    }
+
+    /// `subtitle_delay` in the time base of the subtitles, or 0 until it is known.
+    fn subtitle_delay_pts(&self) -> i64 {
+        match self.timeline {
+            Some(timeline) => timeline.duration_from_secs(self.subtitle_delay),
+            None => 0,
+        }
+    }
 }
 
 impl MediaEngine {
@@ -40,17 +448,114 @@ impl MediaEngine {
         Self {
             next_id: 0,
             tracks: HashMap::new(),
+            probe_cache: None,
+            seek_complete_events: Vec::new(),
+            next_group_id: 0,
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Same as `new`, but every `probe` goes through `cache` so that probing the same path twice
+    /// only opens the file once.
+    pub fn new_with_probe_cache(cache: ProbeCache) -> Self {
+        Self {
+            probe_cache: Some(cache),
+            ..Self::new()
         }
     }
 
+    /// Reads the headers of the file at `path` to find out what it contains, without creating a
+    /// track. This blocks until the file is opened.
+    pub fn probe(&mut self, path: &str) -> Result<Arc<TrackInfo>, MediaError> {
+        match &mut self.probe_cache {
+            Some(cache) => cache.get_or_probe(path),
+            None => Ok(Arc::new(probe_media(&TrackSource::from(path))?)),
+        }
+    }
+
+    /// Saves the part of the file at `source` between `start_secs` and `end_secs` to
+    /// `output_path`. This has nothing to do with the tracks: it opens `source` on its own and
+    /// blocks until the whole segment is written. Nothing is re-encoded, so the segment starts at
+    /// the keyframe before `start_secs`. See `remux_segment`.
+    pub fn transcode_to_file(
+        &self,
+        source: &str,
+        start_secs: f64,
+        end_secs: f64,
+        output_path: &str,
+    ) -> Result<(), MediaError> {
+        remux_segment(
+            &TrackSource::from(source),
+            start_secs,
+            end_secs,
+            output_path,
+        )
+    }
+
     pub fn create_track(&mut self, path: &str) -> TrackId {
-        let worker = spawn_worker_thread();
+        self.create_track_with_config(TrackConfig::new(path))
+    }
+
+    pub fn create_track_with_config(&mut self, config: TrackConfig) -> TrackId {
+        let id = self.add_track(spawn_worker_thread());
+        let track = self.tracks.get_mut(&id).unwrap();
+        track.audio_only = config.audio_only;
 
-        worker
-            .cmd_tx
-            .send(WorkerCommand::Load(path.to_string()))
-            .ok();
+        // Settings go first so that they are already in place when the source is loaded.
+        let commands = [
+            WorkerCommand::SetDecodeErrorPolicy(config.decode_error_policy),
+            WorkerCommand::SetPositionUpdateInterval(config.position_update_interval),
+            WorkerCommand::SetIdleSleep(Duration::from_micros(config.worker_idle_sleep_us)),
+            WorkerCommand::SetMaxQueueMemory(config.max_queue_memory_bytes),
+            WorkerCommand::SetAudioOnly(config.audio_only),
+            WorkerCommand::SetDecoderThreads(config.decoder_threads),
+            WorkerCommand::SetBgraOutput(config.bgra_output),
+            WorkerCommand::SetLowLatency(config.low_latency),
+            WorkerCommand::SetSeekToStart(config.seek_to_start),
+            WorkerCommand::SetStrideAlignment(config.stride_alignment),
+            WorkerCommand::SetSubsampledOutput(config.subsampled_output),
+            WorkerCommand::SetMaxReconnectAttempts(config.max_reconnect_attempts),
+            WorkerCommand::SetSwrOptions(config.swr_options),
+            WorkerCommand::SetFrameCallback(config.frame_callback),
+            WorkerCommand::Load {
+                source: config.source,
+                options: config.format_options,
+            },
+        ];
+        for cmd in commands {
+            track.send_command(cmd);
+        }
+
+        id
+    }
 
+    /// Creates a track that only plays the audio of the file at `path`, for podcasts and music.
+    /// Music files often have their cover art stored as a video stream, which this ignores. Audio
+    /// frames are read with `try_get_audio_frame`.
+    pub fn create_audio_only_track<P: AsRef<Path>>(&mut self, path: P) -> TrackId {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.create_track_with_config(TrackConfig::new(path).with_audio_only(true))
+    }
+
+    /// Creates a track for an HTTP(S) stream, sending `headers` along with every request ffmpeg
+    /// makes for it. Use this for streams that need authentication headers or cookies. A header
+    /// with a line break in it puts the track in an error state rather than being sent.
+    pub fn create_track_from_url(
+        &mut self,
+        url: &str,
+        headers: HashMap<String, String>,
+    ) -> TrackId {
+        let id = self.add_track(spawn_worker_thread());
+        let track = self.tracks.get_mut(&id).unwrap();
+        track.send_command(WorkerCommand::LoadUrl {
+            url: url.to_string(),
+            headers,
+        });
+
+        id
+    }
+
+    fn add_track(&mut self, worker: WorkerHandle) -> TrackId {
         let id = TrackId(self.next_id);
         self.next_id += 1;
 
@@ -61,21 +566,113 @@ impl MediaEngine {
                 worker_state: TrackState::Loading,
                 worker: worker,
                 frame_pool: None,
-                loop_enabled: false,
+                loop_mode: LoopMode::None,
+                playback_region: None,
+                region_end_reached: false,
+                loops_done: 0,
+                pause_on_end: false,
+                forward: true,
+                decode_interval: None,
                 size: None,
-                time_base: None,
-                start_pts: None,
+                bit_depth: None,
+                streams: Vec::new(),
+                attached_picture: None,
+                info: None,
+                timeline: None,
+                current_pts: None,
+                byte_position: None,
+                last_queued_pts: None,
+                seeked: false,
+                max_pts_jump_threshold: 2.0,
+                pts_mapper: None,
                 video_queue: VecDeque::new(),
+                subsampled_queue: VecDeque::new(),
+                frame_hooks: Vec::new(),
+                debug_overlay: false,
+                frames_queued: 0,
+                duplicate_on_stall: false,
+                last_frame: None,
+                audio_only: false,
+                audio_format: None,
+                audio_queue: VecDeque::new(),
+                last_audio_rms: None,
+                subtitle_queue: VecDeque::new(),
+                subtitle_delay: 0.0,
+                warnings: VecDeque::new(),
+                clock: PlaybackClock::default(),
+                stats: PlaybackStats::default(),
             },
         );
 
         id
     }
 
+    pub fn num_tracks(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Iterates over the ids of all the tracks that are alive. The order is arbitrary.
+    pub fn track_ids(&self) -> impl Iterator<Item = TrackId> + '_ {
+        self.tracks.keys().copied()
+    }
+
     pub fn destroy_track(&mut self, id: TrackId) {
         self.tracks.remove(&id);
     }
 
+    /// Creates a track for each of `paths` that are meant to play in sync, e.g. the angles of a
+    /// concert. The tracks are regular tracks otherwise: they are played, paused and read from one
+    /// by one, see `get_group_tracks`. Call `update_group` after `update` to keep them together.
+    pub fn create_track_group(&mut self, paths: &[&str]) -> TrackGroupId {
+        let tracks = paths.iter().map(|path| self.create_track(path)).collect();
+        let id = TrackGroupId(self.next_group_id);
+        self.next_group_id += 1;
+        self.groups.insert(id, tracks);
+        id
+    }
+
+    /// The tracks of the group, in the order of the paths it was created with. Destroyed tracks
+    /// are still listed.
+    pub fn get_group_tracks(&self, group: TrackGroupId) -> Option<&[TrackId]> {
+        self.groups.get(&group).map(Vec::as_slice)
+    }
+
+    /// Seeks every track of the group to `seconds`. The seeks are all sent before any of them is
+    /// processed, so the tracks resume from the same position.
+    pub fn seek_group(&mut self, group: TrackGroupId, seconds: f64) {
+        let Some(tracks) = self.groups.get(&group).cloned() else {
+            return;
+        };
+        for id in tracks {
+            self.seek(id, seconds);
+        }
+    }
+
+    /// Seeks the tracks of the group that fell behind, e.g. because they are more expensive to
+    /// decode, to where the furthest one is. Positions come from the workers' position updates.
+    pub fn update_group(&mut self, group: TrackGroupId) {
+        let Some(tracks) = self.groups.get(&group).cloned() else {
+            return;
+        };
+        let positions: Vec<(TrackId, f64)> = tracks
+            .iter()
+            .filter(|&&id| self.get_state(id) == Some(TrackState::Playing))
+            .filter_map(|&id| {
+                let pts = self.get_current_pts(id)?;
+                Some((id, self.pts_in_seconds(id, pts)?))
+            })
+            .collect();
+        let Some(furthest) = positions.iter().map(|&(_, secs)| secs).reduce(f64::max) else {
+            return;
+        };
+
+        for (id, secs) in positions {
+            if furthest - secs > GROUP_SYNC_TOLERANCE {
+                self.seek(id, furthest);
+            }
+        }
+    }
+
     /// This function is handed over to the user so that they can handle different states properly.
     /// For instance, they should initialize their textures once the track is `Ready`, they should
     /// probably early return if `Loading` display some stuff if `Playing` or `Paused`.
@@ -83,6 +680,133 @@ impl MediaEngine {
         Some(self.tracks.get(&id)?.worker_state.clone())
     }
 
+    /// Same as `probe`, but for the media the track opened. This is `None` until the worker has
+    /// opened it, which is a little before the track becomes `Ready`.
+    pub fn get_track_info(&self, id: TrackId) -> Option<&TrackInfo> {
+        self.tracks.get(&id)?.info.as_ref()
+    }
+
+    /// Calls `update` until the track is done loading and returns its `TrackInfo`, for when there
+    /// is no Bevy app to call `update` for us (tests, CLI tools...). This blocks the calling thread
+    /// for up to `timeout`, and the frames that arrive in the meantime are queued as usual. Fails
+    /// with `MediaError::TrackInfoUnavailable` if the track got past loading without any.
+    pub fn get_track_info_blocking(
+        &mut self,
+        id: TrackId,
+        timeout: Duration,
+    ) -> Result<&TrackInfo, MediaError> {
+        let started_at = Instant::now();
+        loop {
+            self.update();
+            match self.get_state(id) {
+                Some(TrackState::Loading) => {}
+                Some(TrackState::Error(e)) => return Err(MediaError::TrackFailed(e)),
+                Some(_) => break,
+                None => return Err(MediaError::UnknownTrack(id)),
+            }
+            if started_at.elapsed() >= timeout {
+                return Err(MediaError::Timeout);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        self.get_track_info(id)
+            .ok_or(MediaError::TrackInfoUnavailable)
+    }
+
+    /// Plays the track until its first frame is decoded and returns it, e.g. for a splash screen
+    /// or a preview, without having to run `update` every frame. The track is paused again before
+    /// this returns, frame or not. The frame's buffer comes from the pool, so recycle it once done.
+    pub fn get_first_video_frame(
+        &mut self,
+        id: TrackId,
+        timeout: Duration,
+    ) -> Result<VideoFrame, MediaError> {
+        if !self.tracks.contains_key(&id) {
+            return Err(MediaError::UnknownTrack(id));
+        }
+
+        self.play(id);
+        let started_at = Instant::now();
+        let result = loop {
+            self.update();
+            if let Some(frame) = self.try_get_video_frame(id) {
+                break Ok(frame);
+            }
+            if let Some(TrackState::Error(e)) = self.get_state(id) {
+                break Err(MediaError::TrackFailed(e));
+            }
+            if self.is_audio_only(id) {
+                break Err(MediaError::NoVideoStream);
+            }
+            if started_at.elapsed() >= timeout {
+                break Err(MediaError::Timeout);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        self.pause(id);
+        // Lets the worker know right away rather than on the next update.
+        self.update();
+        result
+    }
+
+    /// Swaps the track's frame pool for one with `num_buffers` buffers of the same size, e.g. to
+    /// give a track that keeps running dry more room. Playback carries on as is: frames that were
+    /// decoded into the old pool, whether they are queued or held by you, are recycled into the new
+    /// one like any other. It keeps their buffers while it has room for them and rejects the rest
+    /// (see `PlaybackStats::buffers_rejected`), so there can be a few more than `num_buffers`
+    /// buffers around until then. Reloading the track goes back to the default pool size.
+    pub fn replace_frame_pool(
+        &mut self,
+        id: TrackId,
+        num_buffers: usize,
+    ) -> Result<(), MediaError> {
+        let track = self
+            .tracks
+            .get_mut(&id)
+            .ok_or(MediaError::UnknownTrack(id))?;
+        let Some(old_pool) = &track.frame_pool else {
+            return Err(MediaError::NoVideoStream);
+        };
+
+        let pool = FramePool::new(num_buffers, old_pool.frame_size());
+        track.stats.pool_memory_bytes = pool.memory_usage_bytes();
+        track.frame_pool = Some(pool.clone());
+        track.send_command(WorkerCommand::ReplacePool(pool));
+        Ok(())
+    }
+
+    /// Re-opens the track's source from scratch. This is mostly useful to recover from
+    /// `TrackState::Error`, but it works in any state. The track goes back to `Loading` and
+    /// becomes `Ready` again once the worker has re-initialized it, just like a freshly created
+    /// track. Any queued frames are dropped.
+    pub fn reload(&mut self, id: TrackId) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.desired_state = TrackState::Ready;
+                track.worker_state = TrackState::Loading;
+                track.frame_pool = None;
+                track.size = None;
+                track.bit_depth = None;
+                track.streams.clear();
+                track.timeline = None;
+                track.current_pts = None;
+                track.byte_position = None;
+                track.last_queued_pts = None;
+                track.seeked = false;
+                track.video_queue.clear();
+                track.subsampled_queue.clear();
+                track.frames_queued = 0;
+                track.audio_format = None;
+                track.audio_queue.clear();
+                track.last_audio_rms = None;
+                track.subtitle_queue.clear();
+                track.clock = PlaybackClock::default();
+                track.send_command(WorkerCommand::Reload);
+            }
+            None => {}
+        };
+    }
+
     pub fn play(&mut self, id: TrackId) {
         match self.tracks.get_mut(&id) {
             Some(ref mut track) => track.desired_state = TrackState::Playing,
@@ -90,6 +814,33 @@ impl MediaEngine {
         };
     }
 
+    /// Starts decoding `num_frames` frames ahead of time while the track stays paused, so that the
+    /// first frames are already in the queue by the time `play` is called. Note that the worker
+    /// can't decode more frames than there are buffers in the frame pool until some of them are
+    /// recycled.
+    pub fn prefetch(&mut self, id: TrackId, num_frames: usize) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.send_command(WorkerCommand::Prefetch(num_frames));
+            }
+            None => {}
+        };
+    }
+
+    /// Makes the track pause by itself once `num_frames` more frames have been decoded, e.g. to
+    /// grab a fixed number of frames for a thumbnail grid. This doesn't start playback, so call
+    /// `play` afterwards. The track becomes `Paused` once the last frame is queued. A packet can
+    /// decode into several frames, and the ones that come with the last frame are still queued,
+    /// so there may be more than `num_frames` frames in rare cases.
+    pub fn pause_after_frames(&mut self, id: TrackId, num_frames: u32) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.send_command(WorkerCommand::PauseAfterFrames(num_frames));
+            }
+            None => {}
+        };
+    }
+
     pub fn pause(&mut self, id: TrackId) {
         match self.tracks.get_mut(&id) {
             Some(ref mut track) => track.desired_state = TrackState::Paused,
@@ -98,29 +849,396 @@ impl MediaEngine {
     }
 
     pub fn set_loop(&mut self, id: TrackId, enabled: bool) {
+        let mode = if enabled {
+            LoopMode::Infinite
+        } else {
+            LoopMode::None
+        };
+        self.set_loop_mode(id, mode);
+    }
+
+    pub fn set_loop_mode(&mut self, id: TrackId, mode: LoopMode) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.loop_mode = mode;
+                track.loops_done = 0;
+                // We only ever play backwards in ping-pong mode.
+                if mode != LoopMode::PingPong && !track.forward {
+                    track.forward = true;
+                    track.send_command(WorkerCommand::SetDirection { forward: true });
+                }
+            }
+            None => {}
+        };
+    }
+
+    /// When the track reaches its end without looping, it goes to `TrackState::Paused` on its last
+    /// frame instead of `TrackState::Ended`, like most video players do. It can then be seeked and
+    /// played again as usual.
+    pub fn set_pause_on_end(&mut self, id: TrackId, enabled: bool) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.pause_on_end = enabled,
+            None => {}
+        };
+    }
+
+    /// Only plays the part of the track between `start` and `end`, in seconds: frames outside of it
+    /// are dropped, and reaching `end` counts as reaching the end of the track, so looping goes
+    /// back to `start` rather than to the beginning. This doesn't move the track, so seek to
+    /// `start` to get there right away.
+    pub fn set_playback_region(&mut self, id: TrackId, start: f64, end: f64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.playback_region = Some((start, end));
+                track.region_end_reached = false;
+            }
+            None => {}
+        };
+    }
+
+    /// Plays the whole track again, see `set_playback_region`.
+    pub fn clear_playback_region(&mut self, id: TrackId) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.playback_region = None,
+            None => {}
+        };
+    }
+
+    pub fn get_loop_mode(&self, id: TrackId) -> Option<LoopMode> {
+        Some(self.tracks.get(&id)?.loop_mode)
+    }
+
+    /// Whether the track goes back to the start once it ends, in any of the loop modes. Unknown
+    /// tracks don't loop.
+    pub fn is_looping(&self, id: TrackId) -> bool {
+        self.get_loop_mode(id)
+            .is_some_and(|mode| mode != LoopMode::None)
+    }
+
+    /// Changes how long the worker of the track sleeps while it has nothing to decode, see
+    /// `TrackConfig::with_worker_idle_sleep_us`.
+    pub fn set_worker_idle_sleep_us(&mut self, id: TrackId, micros: u64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.send_command(WorkerCommand::SetIdleSleep(Duration::from_micros(micros)));
+            }
+            None => {}
+        };
+    }
+
+    /// Limits how many frames per second the worker is allowed to decode. Without a limit the
+    /// worker decodes as fast as it can, which is mostly wasted CPU since frames can't be displayed
+    /// faster than the playback clock anyway. A non-positive `fps_limit` removes the limit.
+    pub fn set_max_decode_rate(&mut self, id: TrackId, fps_limit: f64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.decode_interval =
+                    (fps_limit > 0.0).then(|| Duration::from_secs_f64(1.0 / fps_limit));
+                track.send_command(WorkerCommand::SetDecodeRate(fps_limit));
+            }
+            None => {}
+        };
+    }
+
+    pub fn get_max_decode_rate(&self, id: TrackId) -> Option<f64> {
+        let interval = self.tracks.get(&id)?.decode_interval?;
+        Some(1.0 / interval.as_secs_f64())
+    }
+
+    /// Converts the track's frames from YUV into RGB with `matrix`, e.g. `ColorMatrix::Bt709` for
+    /// HD footage whose colors look slightly off. This only affects the colors the scaler outputs:
+    /// frames are still gamma encoded sRGB rather than linear, and HDR frames are not tone mapped.
+    /// Like the filter graph, this doesn't survive a reload. It does nothing for tracks without
+    /// video, or whose video isn't YUV.
+    pub fn apply_color_correction(&mut self, id: TrackId, matrix: ColorMatrix) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.send_command(WorkerCommand::SetColorMatrix(matrix)),
+            None => {}
+        };
+    }
+
+    /// Applies an ffmpeg filtergraph (e.g. `"vflip"`, `"crop=iw/2:ih/2"` or
+    /// `"scale=iw/2:ih/2,boxblur=5"`) to the track's decoded frames. Frames keep the size of the
+    /// video whatever the filter does. Pass an empty string to remove the filter. An invalid
+    /// description puts the track in `TrackState::Error`.
+    pub fn set_filter_graph(&mut self, id: TrackId, description: &str) {
         match self.tracks.get_mut(&id) {
-            Some(ref mut track) => track.loop_enabled = enabled,
+            Some(ref mut track) => {
+                track.send_command(WorkerCommand::SetFilterGraph(description.to_string()));
+            }
             None => {}
         };
     }
 
     pub fn seek(&mut self, id: TrackId, seconds: f64) {
+        self.seek_with_mode(id, seconds, SeekMode::Precise);
+    }
+
+    /// Seeks the track to `seconds`, relative to the start of the video. Frames that are still in
+    /// the queue are recycled since they belong to the old position.
+    pub fn seek_with_mode(&mut self, id: TrackId, seconds: f64, mode: SeekMode) {
         match self.tracks.get_mut(&id) {
             Some(ref mut track) => {
                 track.desired_state = TrackState::Playing;
-                track.worker.cmd_tx.send(WorkerCommand::Seek(seconds)).ok();
+                track.send_command(WorkerCommand::Seek { seconds, mode });
+                track.clock.set(seconds);
+                // The worker lets us know where it landed with its next position update.
+                track.current_pts = None;
+                for frame in track.video_queue.drain(..) {
+                    if let Some(pool) = &track.frame_pool {
+                        pool.recycle(frame.data).ok();
+                    }
+                }
+                track.subsampled_queue.clear();
+                track.audio_queue.clear();
+                track.subtitle_queue.clear();
             }
             None => {}
         };
     }
 
+    /// Jumps ahead to the next keyframe, for fast-forwarding. Unlike seeking, the worker doesn't
+    /// decode anything on the way, it only looks at the packets, so this is cheap. Frames that are
+    /// still in the queue are recycled, and the new position is known once the worker is there.
+    pub fn skip_to_next_keyframe(&mut self, id: TrackId) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.send_command(WorkerCommand::SkipToKeyframe);
+                track.current_pts = None;
+                for frame in track.video_queue.drain(..) {
+                    if let Some(pool) = &track.frame_pool {
+                        pool.recycle(frame.data).ok();
+                    }
+                }
+                track.subsampled_queue.clear();
+                track.audio_queue.clear();
+                track.subtitle_queue.clear();
+            }
+            None => {}
+        };
+    }
+
+    /// Resets the video decoder of the track without seeking, e.g. after feeding it packets it
+    /// choked on. The position doesn't change, but the frames the decoder was still holding are
+    /// lost, and the ones up to the next keyframe may come out broken since their references are
+    /// gone.
+    pub fn flush_decoder(&mut self, id: TrackId) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.send_command(WorkerCommand::FlushDecoder),
+            None => {}
+        };
+    }
+
+    /// Draws the pts of every frame, its index since the track was loaded and how many buffers of
+    /// the frame pool are free onto the top left corner of the frame itself, as it is queued. This
+    /// is meant for debugging, e.g. to see at a glance whether frames are dropped or the pool runs
+    /// dry. The overlay is part of the pixels, so hooks and frame hashes see it too.
+    pub fn set_debug_overlay(&mut self, id: TrackId, enabled: bool) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.debug_overlay = enabled,
+            None => {}
+        };
+    }
+
+    /// Calls `hook` with every frame of the track as it is queued, during `update`, e.g. to log
+    /// frames or analyze them without taking them out of the queue. Hooks are called in the order
+    /// they were registered, and stay until the track is removed. Unlike
+    /// `TrackConfig::with_frame_callback`, this runs on the thread calling `update`, so keep it
+    /// cheap.
+    pub fn register_video_frame_hook<F: Fn(&VideoFrame) + Send + Sync + 'static>(
+        &mut self,
+        id: TrackId,
+        hook: F,
+    ) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.frame_hooks.push(Box::new(hook)),
+            None => {}
+        };
+    }
+
+    /// Only decodes the keyframes of the track, e.g. while the user drags a seek bar. Playback
+    /// jumps from one keyframe to the next (usually a second or more apart), but the worker barely
+    /// has anything to decode, so it keeps up with the fastest scrubbing. Don't forget to turn it
+    /// off once scrubbing is over.
+    pub fn force_keyframe_only_mode(&mut self, id: TrackId, enabled: bool) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.send_command(WorkerCommand::KeyframeOnly(enabled)),
+            None => {}
+        };
+    }
+
     pub fn try_get_video_frame(&mut self, id: TrackId) -> Option<VideoFrame> {
         match self.tracks.get_mut(&id) {
-            Some(ref mut track) => track.video_queue.pop_back(),
+            Some(ref mut track) => match track.video_queue.pop_back() {
+                Some(frame) => {
+                    // Only a frame that leaves the queue empty can be needed again, so it is the
+                    // only one we copy, and into the same allocation every time.
+                    if track.duplicate_on_stall && track.video_queue.is_empty() {
+                        let mut data = track
+                            .last_frame
+                            .take()
+                            .map(|last| last.data)
+                            .unwrap_or_default();
+                        data.clone_from(&frame.data);
+                        track.last_frame = Some(VideoFrame { data, ..frame });
+                    }
+                    Some(frame)
+                }
+                None if track.worker_state == TrackState::Playing => {
+                    let last = track.last_frame.as_ref()?;
+                    // The duplicate goes into a buffer from the pool, so it can be recycled like
+                    // any other frame without the pool ending up with more buffers than it had.
+                    let pool = track.frame_pool.as_ref()?;
+                    let mut data = pool.try_get()?;
+                    if data.len() != last.data.len() {
+                        let _ = pool.recycle(data);
+                        return None;
+                    }
+                    data.copy_from_slice(&last.data);
+                    track.stats.frames_duplicated += 1;
+                    Some(VideoFrame { data, ..*last })
+                }
+                None => None,
+            },
             None => None,
         }
     }
 
+    /// Pops the next half resolution frame, for tracks created with
+    /// `TrackConfig::with_subsampled_output`. These come along with the full frames but are queued
+    /// separately, so they are best read at the same pace. Their buffers don't come from the frame
+    /// pool, so they shouldn't be recycled.
+    pub fn try_get_subsampled_video_frame(&mut self, id: TrackId) -> Option<VideoFrame> {
+        self.tracks.get_mut(&id)?.subsampled_queue.pop_back()
+    }
+
+    /// When enabled, `try_get_video_frame` hands out a copy of the last frame again whenever the
+    /// queue runs dry during playback, so there is always a frame to show, even if it is stale.
+    /// This costs a copy of every frame that empties the queue. Duplicated frames are copied into
+    /// a buffer from the frame pool and should be recycled like any other frame. While every
+    /// buffer is in use there is no duplicate either.
+    pub fn set_duplicate_frame_on_stall(&mut self, id: TrackId, enabled: bool) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
+                track.duplicate_on_stall = enabled;
+                if !enabled {
+                    track.last_frame = None;
+                }
+            }
+            None => {}
+        };
+    }
+
+    /// Pops every queued frame, in the same order as repeatedly calling `try_get_video_frame`.
+    /// Recycling the buffers of the returned frames is up to the caller.
+    pub fn iter_video_frames(&mut self, id: TrackId) -> Option<VideoFrameIter<'_>> {
+        let track = self.tracks.get_mut(&id)?;
+        Some(VideoFrameIter {
+            queue: &mut track.video_queue,
+        })
+    }
+
+    /// Copies the next frame into `dst` and gives its buffer straight back to the pool, returning
+    /// the size of the frame. This is for callers who already have somewhere to put the pixels,
+    /// e.g. a GPU staging buffer. Returns `None` if there is no frame ready, or if `dst` is smaller
+    /// than the frame, in which case the frame is left in the queue.
+    pub fn copy_frame_to_slice(&mut self, id: TrackId, dst: &mut [u8]) -> Option<(u32, u32)> {
+        let track = self.tracks.get_mut(&id)?;
+        if track.video_queue.back()?.data.len() > dst.len() {
+            return None;
+        }
+
+        let frame = track.video_queue.pop_back()?;
+        dst[..frame.data.len()].copy_from_slice(&frame.data);
+        if let Some(pool) = &track.frame_pool {
+            pool.recycle(frame.data).ok();
+        }
+        Some((frame.width, frame.height))
+    }
+
+    /// Pops the next decoded chunk of audio. Audio frames are not throttled by a frame pool like
+    /// video frames are, so they should be consumed as they come in.
+    pub fn try_get_audio_frame(&mut self, id: TrackId) -> Option<AudioFrame> {
+        self.tracks.get_mut(&id)?.audio_queue.pop_back()
+    }
+
+    /// Loudness of the most recently decoded chunk of audio, as its RMS (see `AudioFrame::rms`),
+    /// for audio meters. Chunks are usually 20 to 50ms long, so this is best smoothed before being
+    /// shown. This is `None` until the first chunk arrives, or if the track has no audio.
+    pub fn extract_audio_rms(&self, id: TrackId) -> Option<f32> {
+        self.tracks.get(&id)?.last_audio_rms
+    }
+
+    /// Makes the track's worker write every chunk of audio it decodes to `sink`, on top of queueing
+    /// it for `try_get_audio_frame`. This is the way to feed an audio output (CPAL, kira...) with
+    /// as little latency as possible. Sinks stay in place when the track is reloaded.
+    pub fn add_audio_sink(&mut self, id: TrackId, sink: Arc<dyn AudioSink>) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.send_command(WorkerCommand::AddAudioSink(sink)),
+            None => {}
+        };
+    }
+
+    /// Shifts the track's audio relative to its video: positive values delay the audio, negative
+    /// ones play it early. This is for outputs that add latency of their own, e.g. a TV that
+    /// processes the picture. It applies to the audio frames decoded from now on.
+    pub fn set_audio_delay(&mut self, id: TrackId, seconds: f64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.send_command(WorkerCommand::SetAudioDelay(seconds)),
+            None => {}
+        };
+    }
+
+    /// Sample rate and number of channels of the track's audio. This is `None` until the track is
+    /// `Ready`, or if it has no audio.
+    pub fn get_audio_format(&self, id: TrackId) -> Option<(u32, u16)> {
+        self.tracks.get(&id)?.audio_format
+    }
+
+    /// Whether the track only plays audio, either because it was created with
+    /// `create_audio_only_track` or because its media turned out to have no video stream. The
+    /// latter is only known once the track is `Ready`.
+    pub fn is_audio_only(&self, id: TrackId) -> bool {
+        match self.tracks.get(&id) {
+            Some(track) => {
+                track.audio_only || (track.audio_format.is_some() && track.size.is_none())
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the subtitle that should be on screen at the track's current position, if any. The
+    /// position comes from the worker's position updates, so this is only as accurate as
+    /// `TrackConfig::position_update_interval`.
+    pub fn get_current_subtitle(&self, id: TrackId) -> Option<&SubtitleFrame> {
+        let track = self.tracks.get(&id)?;
+        let current_pts = track.current_pts? - track.subtitle_delay_pts();
+        track
+            .subtitle_queue
+            .iter()
+            .find(|subtitle| subtitle.start_pts <= current_pts && current_pts < subtitle.end_pts)
+    }
+
+    /// Shifts the track's subtitles: positive values show them later, negative ones earlier. This
+    /// is for subtitle files that are off by a constant amount, and applies right away, including
+    /// to the subtitles already decoded.
+    pub fn set_subtitle_delay(&mut self, id: TrackId, seconds: f64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.subtitle_delay = seconds,
+            None => {}
+        };
+    }
+
+    /// Sorts the frames queued for the track by pts, so that `try_get_video_frame` hands them out
+    /// in playback order even if they were received out of it, e.g. frames that were already on
+    /// their way when a seek happened. This is done automatically once a seek completes.
+    pub fn reorder_video_queue(&mut self, id: TrackId) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.sort_video_queue(),
+            None => {}
+        };
+    }
+
     pub fn peek_video_frame(&self, id: TrackId) -> Option<&VideoFrame> {
         match self.tracks.get(&id) {
             Some(track) => track.video_queue.back(),
@@ -128,27 +1246,113 @@ impl MediaEngine {
         }
     }
 
-    pub fn reycle_video_frame_buffer(&self, id: TrackId, buffer: Vec<u8>) {
-        match self.tracks.get(&id) {
-            Some(track) => {
+    /// Copies the next frame without popping it, e.g. to keep it around for comparisons. The copy
+    /// has a buffer of its own rather than one from the frame pool, so it doesn't hold up decoding.
+    /// Don't recycle it: the pool would take it for one of its own buffers, and reject one of
+    /// those later on.
+    pub fn clone_video_frame(&self, id: TrackId) -> Option<VideoFrame> {
+        self.peek_video_frame(id).cloned()
+    }
+
+    /// Borrows the next frame without popping it, e.g. to check its pts before deciding whether it
+    /// is time to show it. Recycling it through the `VideoFrameRef` pops it, and simply dropping
+    /// the `VideoFrameRef` leaves it in the queue.
+    pub fn current_video_frame_ref(&mut self, id: TrackId) -> Option<VideoFrameRef<'_>> {
+        let track = self.tracks.get_mut(&id)?;
+        track.video_queue.back()?;
+        Some(VideoFrameRef {
+            queue: &mut track.video_queue,
+            pool: track.frame_pool.as_ref(),
+        })
+    }
+
+    /// Gives the buffer of a frame back to the track's frame pool. Buffers the pool rejects are
+    /// counted in `PlaybackStats::buffers_rejected`.
+    pub fn reycle_video_frame_buffer(&mut self, id: TrackId, buffer: Vec<u8>) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => {
                 let Some(pool) = &track.frame_pool else {
                     return;
                 };
-                pool.recycle(buffer).ok();
+                if pool.recycle(buffer).is_err() {
+                    track.stats.buffers_rejected += 1;
+                }
             }
             None => {}
         }
     }
 
+    /// For how long the track has been playing, in seconds, pauses excluded. Seeking moves it to
+    /// the seek target and looping back to the start moves it back to 0, so this is where the
+    /// track should be in the video: compare it to `pts_in_seconds` of the queued frames to know
+    /// which one to show.
+    pub fn elapsed_playback_secs(&self, id: TrackId) -> Option<f64> {
+        Some(self.tracks.get(&id)?.clock.elapsed_secs())
+    }
+
+    /// Start and end of the track's media in seconds, on the media's own timeline rather than
+    /// relative to the first frame like positions are. See `pts_range_in_seconds`.
+    pub fn get_time_range(&self, id: TrackId) -> Option<(f64, f64)> {
+        self.tracks.get(&id)?.info.as_ref()?.time_range
+    }
+
+    /// Where the track's worker is reading the input, in bytes from its start, e.g. to show how far
+    /// into a large file playback is when its duration is unknown. The worker reads ahead of what
+    /// is shown, and this is only updated along with the position (see
+    /// `TrackConfig::position_update_interval`), so this is a rough figure. `None` until the first
+    /// update, or if ffmpeg can't tell (some network streams and devices).
+    pub fn get_packet_position(&self, id: TrackId) -> Option<u64> {
+        self.tracks.get(&id)?.byte_position
+    }
+
     pub fn pts_in_seconds(&self, id: TrackId, pts: i64) -> Option<f64> {
-        match self.tracks.get(&id) {
-            Some(track) => {
-                let relative_pts = pts - track.start_pts?;
-                let microseconds =
-                    relative_pts.rescale(track.time_base?, ffmpeg::mathematics::rescale::TIME_BASE);
-                Some(microseconds as f64 / 1_000_000.0)
-            }
-            None => None,
+        self.tracks.get(&id)?.pts_in_seconds(pts)
+    }
+
+    /// Sets how far apart, in seconds, two consecutive frames have to be for the jump to be
+    /// reported as a `TrackSeekCompleteEvent`. Defaults to 2 seconds. This is measured on the pts
+    /// the stream has, so that resets the track smooths over (see `PtsMapper`) are reported too.
+    pub fn set_max_pts_jump_threshold(&mut self, id: TrackId, seconds: f64) {
+        match self.tracks.get_mut(&id) {
+            Some(ref mut track) => track.max_pts_jump_threshold = seconds,
+            None => {}
+        };
+    }
+
+    /// Returns the seek/discontinuity events of all tracks since the last call.
+    pub fn drain_seek_complete_events(&mut self) -> Vec<TrackSeekCompleteEvent> {
+        std::mem::take(&mut self.seek_complete_events)
+    }
+
+    /// The position of the worker's decode head, as of its last position update. Unlike the pts of
+    /// the frames in the queue, this is kept up to date while paused too, which is handy for seek
+    /// previews. Use `pts_in_seconds` to convert it.
+    pub fn get_current_pts(&self, id: TrackId) -> Option<i64> {
+        self.tracks.get(&id)?.current_pts
+    }
+
+    /// Asks the worker where its decode head is, without waiting for its next position update.
+    /// The answer arrives with the next `update`, so this returns what `get_current_pts` does
+    /// until then.
+    pub fn query_current_pts(&mut self, id: TrackId) -> Option<i64> {
+        let track = self.tracks.get_mut(&id)?;
+        track.send_command(WorkerCommand::GetCurrentPts);
+        track.current_pts
+    }
+
+    /// A snapshot of the track's counters, which can be kept around (or serialized, with the
+    /// `serde` feature) while the track keeps going.
+    pub fn get_playback_stats(&self, id: TrackId) -> Option<PlaybackStats> {
+        Some(self.tracks.get(&id)?.stats.clone())
+    }
+
+    /// Takes the warnings the track's worker reported since the last call, oldest first. These are
+    /// issues that don't stop playback, e.g. timestamps going backwards, which is mostly useful
+    /// for debug overlays. Only the last few are kept.
+    pub fn drain_warnings(&mut self, id: TrackId) -> Vec<String> {
+        match self.tracks.get_mut(&id) {
+            Some(track) => track.warnings.drain(..).collect(),
+            None => Vec::new(),
         }
     }
 
@@ -156,46 +1360,240 @@ impl MediaEngine {
         self.tracks.get(&id)?.size
     }
 
+    /// Bits per color component of the track's video. Frames of videos with a bit depth above 8
+    /// come in `RGBA64LE` rather than `RGBA`, so they should be uploaded to a 16-bit texture.
+    pub fn get_bit_depth(&self, id: TrackId) -> Option<u8> {
+        self.tracks.get(&id)?.bit_depth
+    }
+
+    /// The encoded image (usually JPEG or PNG) attached to the track's media, e.g. album art. This
+    /// is `None` until the track is `Ready`.
+    pub fn get_attached_picture(&self, id: TrackId) -> Option<&[u8]> {
+        self.tracks.get(&id)?.attached_picture.as_deref()
+    }
+
+    /// Lists every stream in the track's container, including the ones we don't decode. This is
+    /// empty until the track is `Ready`.
+    pub fn get_available_streams(&self, id: TrackId) -> Option<&[StreamDescriptor]> {
+        Some(&self.tracks.get(&id)?.streams)
+    }
+
+    /// Runs `update` over and over and recycles every video frame that comes in, until
+    /// `max_frames` frames went by or no frame came in for a little while (e.g. every track is
+    /// paused or ended). This is for tests and tools, to get the engine to a known state without
+    /// running an app. Returns how many frames were recycled, across all tracks.
+    pub fn drain(&mut self, max_frames: usize) -> usize {
+        let mut drained = 0;
+        let mut last_frame_at = Instant::now();
+        while drained < max_frames && last_frame_at.elapsed() < DRAIN_IDLE_TIMEOUT {
+            self.update();
+            for track in self.tracks.values_mut() {
+                for frame in track.video_queue.drain(..) {
+                    if let Some(pool) = &track.frame_pool {
+                        pool.recycle(frame.data).ok();
+                    }
+                    drained += 1;
+                    last_frame_at = Instant::now();
+                }
+                track.subsampled_queue.clear();
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drained
+    }
+
     pub fn update(&mut self) {
-        for track in self.tracks.values_mut() {
+        for (&id, track) in self.tracks.iter_mut() {
             while let Ok(msg) = track.worker.msg_rx.try_recv() {
                 match msg {
+                    WorkerMessage::TrackInfo(info) => track.info = Some(info),
                     WorkerMessage::Initialized {
                         pool,
                         width,
                         height,
+                        bit_depth,
                         time_base,
-                        start_pts,
+                        first_pts,
+                        streams,
+                        attached_picture,
                     } => {
+                        // A live stream that was reopened carries on where it was, even if its
+                        // timestamps start over, which is what the mapper is there for.
+                        let reconnected =
+                            matches!(track.worker_state, TrackState::Reconnecting { .. });
                         track.worker_state = TrackState::Ready;
+                        track.stats.pool_memory_bytes = pool.memory_usage_bytes();
                         track.frame_pool = Some(pool);
                         track.size = Some((width, height));
-                        track.time_base = Some(time_base);
-                        track.start_pts = Some(start_pts);
+                        track.bit_depth = Some(bit_depth);
+                        track.streams = streams;
+                        track.attached_picture = attached_picture;
+                        if !reconnected || track.pts_mapper.is_none() {
+                            let timeline = PtsToWallClock::new(time_base, first_pts);
+                            track.timeline = Some(timeline);
+                            // Anything that goes back further than what we report as a seek is a
+                            // reset.
+                            let threshold =
+                                timeline.duration_from_secs(track.max_pts_jump_threshold);
+                            track.pts_mapper = Some(PtsMapper::new(threshold));
+                        }
+                    }
+                    WorkerMessage::AudioInitialized {
+                        sample_rate,
+                        channels,
+                        time_base,
+                        start_pts,
+                    } => {
+                        track.audio_format = Some((sample_rate, channels));
+                        // Without video, audio is what keeps track of the position.
+                        if track.size.is_none() {
+                            track.timeline = Some(PtsToWallClock::new(time_base, start_pts));
+                        }
+                        if track.worker_state == TrackState::Loading {
+                            track.worker_state = TrackState::Ready;
+                        }
                     }
-                    WorkerMessage::VideoFrame(frame) => {
+                    WorkerMessage::VideoFrame(mut frame) => {
+                        if frame.metadata.pts_synthesized {
+                            track.stats.synthesized_pts_count += 1;
+                        }
+                        let stream_pts = frame.pts;
+                        if let Some(pts) = frame.pts
+                            && let Some(mapper) = &mut track.pts_mapper
+                        {
+                            frame.pts = Some(mapper.map(pts));
+                        }
+                        if let Some((start, end)) = track.playback_region
+                            && let Some(secs) = frame.pts.and_then(|pts| track.pts_in_seconds(pts))
+                            && !(start..=end).contains(&secs)
+                        {
+                            if let Some(pool) = &track.frame_pool {
+                                pool.recycle(frame.data).ok();
+                            }
+                            // Frames behind us are simply skipped, e.g. the ones between the
+                            // keyframe we seeked to and the start of the region.
+                            let ahead = if track.forward {
+                                secs > end
+                            } else {
+                                secs < start
+                            };
+                            if ahead && !track.region_end_reached {
+                                track.region_end_reached = true;
+                                track.reach_end();
+                                if track.worker_state == TrackState::Ended {
+                                    // Unlike at the end of the stream, there is more to decode.
+                                    track.send_command(WorkerCommand::Pause);
+                                    track.desired_state = TrackState::Ended;
+                                }
+                            }
+                            continue;
+                        }
+                        let discontinuity =
+                            match (stream_pts, track.last_queued_pts, track.timeline) {
+                                (Some(pts), Some(last_pts), Some(timeline)) => {
+                                    timeline.duration_to_secs(pts - last_pts).abs()
+                                        > track.max_pts_jump_threshold
+                                }
+                                _ => false,
+                            };
+                        if let Some(pts) = frame.pts
+                            && (track.seeked || discontinuity)
+                        {
+                            self.seek_complete_events
+                                .push(TrackSeekCompleteEvent { track_id: id, pts });
+                            track.seeked = false;
+                        }
+                        track.last_queued_pts = stream_pts.or(track.last_queued_pts);
+                        track.frames_queued += 1;
+                        if track.debug_overlay {
+                            let pts = frame.pts.map_or("-".to_string(), |pts| pts.to_string());
+                            let (free, capacity) =
+                                track.frame_pool.as_ref().map_or((0, 0), |pool| {
+                                    (pool.snapshot_free_count(), pool.capacity())
+                                });
+                            let text = format!(
+                                "PTS: {pts} | Frame: {} | Pool: {free}/{capacity}",
+                                track.frames_queued
+                            );
+                            draw_text(&mut frame, &text);
+                        }
+                        for hook in &track.frame_hooks {
+                            hook(&frame);
+                        }
                         track.video_queue.push_front(frame);
                     }
-                    WorkerMessage::Error(e) => track.worker_state = TrackState::Error(e),
-                    WorkerMessage::EndOfStream => {
-                        if track.loop_enabled {
-                            track.worker.cmd_tx.send(WorkerCommand::Seek(0.0)).ok();
-                            track.worker_state = TrackState::Playing;
-                        } else {
-                            track.worker_state = TrackState::Ended;
+                    WorkerMessage::SubsampledVideoFrame(frame) => {
+                        track.subsampled_queue.push_front(frame);
+                        track
+                            .subsampled_queue
+                            .truncate(MAX_QUEUED_SUBSAMPLED_FRAMES);
+                    }
+                    WorkerMessage::AudioFrame(frame) => {
+                        track.last_audio_rms = Some(frame.rms);
+                        track.audio_queue.push_front(frame);
+                        // Nobody may be reading the audio of a video track, so we drop the oldest
+                        // frames rather than letting the queue grow forever.
+                        track.audio_queue.truncate(MAX_QUEUED_AUDIO_FRAMES);
+                    }
+                    WorkerMessage::SubtitleFrame(subtitle) => {
+                        track.subtitle_queue.push_back(subtitle)
+                    }
+                    WorkerMessage::Seeked => {
+                        if let Some(mapper) = &mut track.pts_mapper {
+                            mapper.reset();
+                        }
+                        track.seeked = true;
+                        track.region_end_reached = false;
+                        track.sort_video_queue();
+                    }
+                    WorkerMessage::PositionUpdate { pts, byte_position } => {
+                        track.byte_position = byte_position;
+                        track.set_current_pts(pts);
+                    }
+                    WorkerMessage::CurrentPts(pts) => track.set_current_pts(pts),
+                    WorkerMessage::Reconnecting { attempt } => {
+                        track.worker_state = TrackState::Reconnecting { attempt };
+                    }
+                    WorkerMessage::FrameDeduped => track.stats.frames_deduped += 1,
+                    WorkerMessage::PausedAfterFrames => {
+                        // The worker already paused, we only have to agree with it.
+                        track.desired_state = TrackState::Paused;
+                        track.worker_state = TrackState::Paused;
+                        track.clock.pause();
+                    }
+                    WorkerMessage::Warning(warning) => {
+                        if track.warnings.len() == MAX_WARNINGS {
+                            track.warnings.pop_front();
                         }
+                        track.warnings.push_back(warning);
                     }
+                    WorkerMessage::Error(e) => track.worker_state = TrackState::Error(e),
+                    WorkerMessage::EndOfStream => track.reach_end(),
                 }
             }
+            if !track.worker.is_alive() && !matches!(track.worker_state, TrackState::Error(_)) {
+                track.worker_state = TrackState::Error("worker died".into());
+            }
+            // A track in an error state stays there until it is reloaded, and a reconnecting one
+            // until it is re-initialized.
+            if matches!(
+                track.worker_state,
+                TrackState::Error(_) | TrackState::Reconnecting { .. }
+            ) {
+                continue;
+            }
+
             if track.worker_state != track.desired_state {
                 match track.desired_state {
                     TrackState::Playing => {
-                        track.worker.cmd_tx.send(WorkerCommand::Play).ok();
+                        track.send_command(WorkerCommand::Play);
                         track.worker_state = TrackState::Playing;
+                        track.clock.start();
                     }
                     TrackState::Paused => {
-                        track.worker.cmd_tx.send(WorkerCommand::Pause).ok();
+                        track.send_command(WorkerCommand::Pause);
                         track.worker_state = TrackState::Paused;
+                        track.clock.pause();
                     }
                     // If the desired state is not one of them, we ignore them as it doesn't quite
                     // make sense
@@ -205,3 +1603,300 @@ impl MediaEngine {
         }
     }
 }
+
+/// A handle to a `MediaEngine` that can be cloned, so that several owners drive the same tracks,
+/// e.g. the main world and the render world, which can't share a resource. Every clone locks the
+/// same engine. `BevyFfmpegPlugin` keeps it updated if the app has one.
+#[cfg(feature = "thread_safe")]
+#[derive(Resource, Clone)]
+pub struct SharedMediaEngine(Arc<Mutex<MediaEngine>>);
+
+#[cfg(feature = "thread_safe")]
+impl SharedMediaEngine {
+    pub fn new(engine: MediaEngine) -> Self {
+        Self(Arc::new(Mutex::new(engine)))
+    }
+
+    /// Locks the engine until the guard is dropped. A panic while it was locked doesn't leave the
+    /// tracks in a state worse than any other, so we don't care about poisoning.
+    pub fn lock(&self) -> MutexGuard<'_, MediaEngine> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::VideoFrameMetadata;
+    use crate::worker::detached_worker;
+    use crossbeam_channel::{Receiver, Sender};
+
+    /// A track whose worker is played by the test, already initialized with a millisecond time
+    /// base. The command receiver has to be kept around, or the track would see its worker gone.
+    fn engine_with_track() -> (
+        MediaEngine,
+        TrackId,
+        Receiver<WorkerCommand>,
+        Sender<WorkerMessage>,
+    ) {
+        let mut engine = MediaEngine::new();
+        let (worker, cmd_rx, msg_tx) = detached_worker();
+        let id = engine.add_track(worker);
+        msg_tx
+            .send(WorkerMessage::Initialized {
+                width: 1,
+                height: 1,
+                bit_depth: 8,
+                pool: FramePool::new(8, 4),
+                time_base: ffmpeg::Rational::new(1, 1000),
+                first_pts: 0,
+                streams: Vec::new(),
+                attached_picture: None,
+            })
+            .unwrap();
+        engine.update();
+        (engine, id, cmd_rx, msg_tx)
+    }
+
+    fn frame(pts: i64) -> VideoFrame {
+        VideoFrame {
+            width: 1,
+            height: 1,
+            stride: 4,
+            data: vec![0; 4],
+            pts: Some(pts),
+            dts: Some(pts),
+            pixel_format: ffmpeg::format::Pixel::RGBA,
+            sample_aspect_ratio: None,
+            hdr_metadata: None,
+            crop: None,
+            metadata: VideoFrameMetadata::default(),
+        }
+    }
+
+    /// Sends frames with the given pts and returns the pts of the events they caused.
+    fn queue_frames(
+        engine: &mut MediaEngine,
+        msg_tx: &Sender<WorkerMessage>,
+        pts: &[i64],
+    ) -> Vec<i64> {
+        for &pts in pts {
+            msg_tx.send(WorkerMessage::VideoFrame(frame(pts))).unwrap();
+        }
+        engine.update();
+        engine
+            .drain_seek_complete_events()
+            .into_iter()
+            .map(|event| event.pts)
+            .collect()
+    }
+
+    #[test]
+    fn steady_frames_cause_no_event() {
+        let (mut engine, _id, _cmd_rx, msg_tx) = engine_with_track();
+        assert_eq!(queue_frames(&mut engine, &msg_tx, &[0, 40, 80, 120]), []);
+    }
+
+    #[test]
+    fn short_seeks_are_reported() {
+        let (mut engine, _id, _cmd_rx, msg_tx) = engine_with_track();
+        queue_frames(&mut engine, &msg_tx, &[0, 40]);
+
+        msg_tx.send(WorkerMessage::Seeked).unwrap();
+        // Well below the threshold, but only the first frame at the new position is reported.
+        assert_eq!(queue_frames(&mut engine, &msg_tx, &[500, 540]), [500]);
+        msg_tx.send(WorkerMessage::Seeked).unwrap();
+        assert_eq!(queue_frames(&mut engine, &msg_tx, &[100]), [100]);
+    }
+
+    #[test]
+    fn jumps_past_the_threshold_are_reported() {
+        let (mut engine, _id, _cmd_rx, msg_tx) = engine_with_track();
+        assert_eq!(
+            queue_frames(&mut engine, &msg_tx, &[0, 40, 10_000, 10_040]),
+            [10_000]
+        );
+    }
+
+    #[test]
+    fn stream_resets_are_reported_with_the_mapped_pts() {
+        let (mut engine, _id, _cmd_rx, msg_tx) = engine_with_track();
+        // The mapper carries on right after the last frame, and the event has to match the pts
+        // of the frames in the queue.
+        assert_eq!(
+            queue_frames(&mut engine, &msg_tx, &[10_000, 10_040, 0, 40]),
+            [10_041]
+        );
+    }
+
+    #[test]
+    fn reconnecting_keeps_the_timeline_going() {
+        let (mut engine, id, _cmd_rx, msg_tx) = engine_with_track();
+        queue_frames(&mut engine, &msg_tx, &[10_000, 10_040]);
+
+        msg_tx
+            .send(WorkerMessage::Reconnecting { attempt: 1 })
+            .unwrap();
+        // The reopened stream starts its timestamps over, from a different first pts.
+        msg_tx
+            .send(WorkerMessage::Initialized {
+                width: 1,
+                height: 1,
+                bit_depth: 8,
+                pool: FramePool::new(8, 4),
+                time_base: ffmpeg::Rational::new(1, 1000),
+                first_pts: 500,
+                streams: Vec::new(),
+                attached_picture: None,
+            })
+            .unwrap();
+        queue_frames(&mut engine, &msg_tx, &[500]);
+
+        let queued: Vec<_> = std::iter::from_fn(|| engine.try_get_video_frame(id))
+            .map(|frame| frame.pts)
+            .collect();
+        assert_eq!(queued, [Some(10_000), Some(10_040), Some(10_041)]);
+        assert_eq!(engine.pts_in_seconds(id, 10_041), Some(10.041));
+    }
+
+    #[test]
+    fn mapper_leaves_forward_jumps_alone() {
+        let mut mapper = PtsMapper::new(100);
+        assert_eq!(mapper.map(0), 0);
+        assert_eq!(mapper.map(40), 40);
+        assert_eq!(mapper.map(100_000), 100_000);
+        assert_eq!(mapper.offset(), 0);
+    }
+
+    #[test]
+    fn mapper_leaves_small_backward_jumps_alone() {
+        let mut mapper = PtsMapper::new(100);
+        mapper.map(1_000);
+        // Reordered frames, e.g. B-frames with a broken pts.
+        assert_eq!(mapper.map(950), 950);
+        assert_eq!(mapper.map(900), 900);
+        assert_eq!(mapper.offset(), 0);
+    }
+
+    #[test]
+    fn mapper_carries_on_after_a_reset() {
+        let mut mapper = PtsMapper::new(100);
+        mapper.map(1_000);
+        assert_eq!(mapper.map(0), 1_001);
+        assert_eq!(mapper.offset(), 1_001);
+        assert_eq!(mapper.map(40), 1_041);
+    }
+
+    #[test]
+    fn mapper_handles_repeated_resets() {
+        let mut mapper = PtsMapper::new(100);
+        mapper.map(1_000);
+        mapper.map(0);
+        assert_eq!(mapper.map(500), 1_501);
+        assert_eq!(mapper.map(0), 1_502);
+        assert_eq!(mapper.offset(), 1_502);
+        assert_eq!(mapper.map(40), 1_542);
+
+        mapper.reset();
+        assert_eq!(mapper.map(0), 0);
+    }
+
+    #[test]
+    fn stalls_duplicate_the_last_frame_into_a_pool_buffer() {
+        let (mut engine, id, _cmd_rx, msg_tx) = engine_with_track();
+        engine.set_duplicate_frame_on_stall(id, true);
+        engine.play(id);
+        queue_frames(&mut engine, &msg_tx, &[0]);
+        let last = VideoFrame {
+            data: vec![1, 2, 3, 4],
+            ..frame(40)
+        };
+        msg_tx
+            .send(WorkerMessage::VideoFrame(last.clone()))
+            .unwrap();
+        engine.update();
+        engine.try_get_video_frame(id).unwrap();
+        assert_eq!(engine.try_get_video_frame(id).unwrap().pts, Some(40));
+
+        let pool = engine.tracks[&id].frame_pool.clone().unwrap();
+        let duplicate = engine.try_get_video_frame(id).unwrap();
+        assert_eq!((duplicate.pts, &duplicate.data), (last.pts, &last.data));
+        assert_eq!(pool.snapshot_free_count(), 7);
+        engine.reycle_video_frame_buffer(id, duplicate.data);
+        assert_eq!(pool.snapshot_free_count(), 8);
+
+        // Without a free buffer there is nothing to copy the frame into.
+        let buffers: Vec<_> = std::iter::from_fn(|| pool.try_get()).collect();
+        assert!(engine.try_get_video_frame(id).is_none());
+        for buffer in buffers {
+            pool.recycle(buffer).unwrap();
+        }
+        assert!(engine.try_get_video_frame(id).is_some());
+    }
+
+    #[test]
+    fn pools_are_replaced_while_frames_are_held() {
+        let (mut engine, id, cmd_rx, msg_tx) = engine_with_track();
+        let old_pool = engine.tracks[&id].frame_pool.clone().unwrap();
+        let held = old_pool.get().unwrap();
+        msg_tx
+            .send(WorkerMessage::VideoFrame(VideoFrame {
+                data: old_pool.get().unwrap(),
+                ..frame(0)
+            }))
+            .unwrap();
+        engine.update();
+
+        engine.replace_frame_pool(id, 2).unwrap();
+        assert!(
+            cmd_rx
+                .try_iter()
+                .any(|command| matches!(command, WorkerCommand::ReplacePool(_)))
+        );
+        let pool = engine.tracks[&id].frame_pool.clone().unwrap();
+        assert_eq!((pool.capacity(), pool.snapshot_free_count()), (2, 2));
+
+        // Buffers of the old pool go to the new one when it has room for them.
+        let queued = engine.try_get_video_frame(id).unwrap();
+        pool.get().unwrap();
+        engine.reycle_video_frame_buffer(id, queued.data);
+        engine.reycle_video_frame_buffer(id, held);
+        assert_eq!(pool.snapshot_free_count(), 2);
+        assert_eq!(engine.get_playback_stats(id).unwrap().buffers_rejected, 1);
+    }
+
+    #[test]
+    fn pts_convert_to_seconds_from_the_start() {
+        let clock = PtsToWallClock::new(ffmpeg::Rational::new(1, 90_000), 9_000);
+        assert_eq!(clock.to_secs(9_000), 0.0);
+        assert_eq!(clock.to_secs(99_000), 1.0);
+        assert_eq!(clock.to_secs(0), -0.1);
+        assert_eq!(clock.from_secs(1.0), 99_000);
+        assert_eq!(clock.from_secs(0.5), 54_000);
+        assert_eq!(clock.duration_to_secs(45_000), 0.5);
+        assert_eq!(clock.duration_from_secs(2.0), 180_000);
+    }
+
+    #[test]
+    fn playback_clock_stands_still_while_paused() {
+        let mut clock = PlaybackClock::default();
+        assert_eq!(clock.elapsed_secs(), 0.0);
+        clock.set(5.0);
+        assert_eq!(clock.elapsed_secs(), 5.0);
+
+        clock.start();
+        std::thread::sleep(Duration::from_millis(10));
+        clock.pause();
+        let paused_at = clock.elapsed_secs();
+        assert!(paused_at >= 5.01);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(clock.elapsed_secs(), paused_at);
+
+        // Setting a running clock keeps it running from there.
+        clock.start();
+        clock.set(1.0);
+        std::thread::sleep(Duration::from_millis(10));
+        let elapsed = clock.elapsed_secs();
+        assert!(elapsed >= 1.01 && elapsed < 5.0);
+    }
+}
@@ -0,0 +1,98 @@
+use super::engine::TrackId;
+use ffmpeg_next as ffmpeg;
+use std::fmt;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MediaError {
+    /// Serialized as ffmpeg's error code, e.g. `AVERROR_EOF`.
+    #[cfg_attr(feature = "serde", serde(with = "ffmpeg_error_code"))]
+    Ffmpeg(ffmpeg::Error),
+    /// The operation needs a video stream, but the session doesn't have one.
+    NoVideoStream,
+    /// The operation needs an audio stream, but the session doesn't have one.
+    NoAudioStream,
+    /// A buffer of the wrong size was recycled into a `FramePool`.
+    BufferSizeMismatch { expected: usize, got: usize },
+    /// A buffer was recycled into a `FramePool` that already has all of its buffers.
+    PoolFull,
+    /// A buffer is too small for the frame that should be written into it.
+    BufferTooSmall { required: usize, got: usize },
+    /// The other end of a channel was dropped.
+    ChannelClosed,
+    /// There is no track with this id.
+    UnknownTrack(TrackId),
+    /// The track went into `TrackState::Error` while we were waiting for it.
+    TrackFailed(String),
+    /// An HTTP header has an invalid name, or a line break in its name or value, see
+    /// `MediaEngine::create_track_from_url`.
+    InvalidHttpHeader(String),
+    /// We gave up waiting for something.
+    Timeout,
+    /// The track is done loading, but its worker never sent its `TrackInfo`.
+    TrackInfoUnavailable,
+    /// No frame was found at the requested position, see `frame_at_pts`.
+    FrameNotFound,
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::Ffmpeg(e) => write!(f, "ffmpeg error: {e}"),
+            MediaError::NoVideoStream => write!(f, "the media has no video stream"),
+            MediaError::NoAudioStream => write!(f, "the media has no audio stream"),
+            MediaError::BufferSizeMismatch { expected, got } => write!(
+                f,
+                "buffer has the wrong size: expected {expected} bytes, got {got}"
+            ),
+            MediaError::PoolFull => write!(f, "the pool already has all of its buffers"),
+            MediaError::BufferTooSmall { required, got } => write!(
+                f,
+                "buffer is too small: {required} bytes are required, got {got}"
+            ),
+            MediaError::ChannelClosed => write!(f, "channel closed"),
+            MediaError::UnknownTrack(id) => write!(f, "no track with id {id:?}"),
+            MediaError::TrackFailed(e) => write!(f, "track failed: {e}"),
+            MediaError::InvalidHttpHeader(name) => write!(f, "invalid HTTP header {name:?}"),
+            MediaError::Timeout => write!(f, "timed out"),
+            MediaError::TrackInfoUnavailable => write!(f, "the track has no track info"),
+            MediaError::FrameNotFound => write!(f, "no frame found at the requested position"),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MediaError::Ffmpeg(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// `ffmpeg::Error` doesn't implement serde's traits, but it converts to and from the `AVERROR`
+/// code it stands for.
+#[cfg(feature = "serde")]
+mod ffmpeg_error_code {
+    use ffmpeg_next as ffmpeg;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        error: &ffmpeg::Error,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(i32::from(*error))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ffmpeg::Error, D::Error> {
+        Ok(ffmpeg::Error::from(i32::deserialize(deserializer)?))
+    }
+}
+
+impl From<ffmpeg::Error> for MediaError {
+    fn from(e: ffmpeg::Error) -> Self {
+        MediaError::Ffmpeg(e)
+    }
+}
@@ -1,11 +1,66 @@
-use crossbeam_channel::{Receiver, RecvError, SendError, Sender, bounded};
+use super::error::MediaError;
+use crossbeam_channel::{Receiver, RecvError, Sender, TrySendError, bounded};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
 
-#[derive(Debug, Clone)]
-pub struct FramePool {
+/// Allocates the buffers of a `FramePool`. The returned `Vec` is dropped like any other `Vec`, so
+/// its memory has to come from the global allocator. Within that constraint, an allocator can
+/// still e.g. align the buffer or `madvise` it into transparent huge pages.
+pub trait Allocator {
+    fn allocate(size: usize) -> Vec<u8>;
+}
+
+/// Plain zeroed `Vec`s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultAllocator;
+
+impl Allocator for DefaultAllocator {
+    fn allocate(size: usize) -> Vec<u8> {
+        vec![0u8; size]
+    }
+}
+
+/// A fixed set of buffers that frames are decoded into and recycled back into, so that decoding
+/// doesn't allocate. Clones of a pool are the same pool, see `shared_clone`.
+pub struct FramePool<A: Allocator = DefaultAllocator> {
+    shared: Arc<FreeBuffers>,
+    frame_size: usize,
+    capacity: usize,
+    // `fn() -> A` so that the pool is `Send` and `Sync` whatever the allocator is.
+    _allocator: PhantomData<fn() -> A>,
+}
+
+/// Both ends of the channel the free buffers sit in, shared by every handle to the pool.
+#[derive(Debug)]
+struct FreeBuffers {
     free_rx: Receiver<Vec<u8>>,
     free_tx: Sender<Vec<u8>>,
 }
 
+// These are implemented by hand since deriving them would require `A` to implement them too.
+impl<A: Allocator> Clone for FramePool<A> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            frame_size: self.frame_size,
+            capacity: self.capacity,
+            _allocator: PhantomData,
+        }
+    }
+}
+
+impl<A: Allocator> fmt::Debug for FramePool<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FramePool")
+            .field("free_rx", &self.shared.free_rx)
+            .field("free_tx", &self.shared.free_tx)
+            .field("frame_size", &self.frame_size)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
 impl FramePool {
     /// `num_buffers` is the number of different buffers allocated. The more you have, the more
     /// memory you allocate.
@@ -14,22 +69,89 @@ impl FramePool {
     /// RGBA8 (it is for us), then this should be equal to `width * height * 4` since each pixel is
     /// 4 bytes long.
     pub fn new(num_buffers: usize, frame_size: usize) -> Self {
+        Self::with_allocator(num_buffers, frame_size)
+    }
+}
+
+impl<A: Allocator> FramePool<A> {
+    /// Same as `FramePool::new`, but the buffers are allocated by `A`. The allocator only matters
+    /// here, so it costs nothing afterwards.
+    pub fn with_allocator(num_buffers: usize, frame_size: usize) -> Self {
         let (tx, rx) = bounded(num_buffers);
         for _ in 0..num_buffers {
-            tx.send(vec![0u8; frame_size])
+            tx.send(A::allocate(frame_size))
                 .expect("Couldn't setup buffers for ffmpeg");
         }
         Self {
-            free_tx: tx,
-            free_rx: rx,
+            shared: Arc::new(FreeBuffers {
+                free_rx: rx,
+                free_tx: tx,
+            }),
+            frame_size,
+            capacity: num_buffers,
+            _allocator: PhantomData,
         }
     }
 
     pub fn get(&self) -> Result<Vec<u8>, RecvError> {
-        return self.free_rx.recv();
+        return self.shared.free_rx.recv();
+    }
+
+    /// Like `get`, but returns `None` right away instead of waiting when every buffer is in use.
+    pub fn try_get(&self) -> Option<Vec<u8>> {
+        return self.shared.free_rx.try_recv().ok();
+    }
+
+    /// Gives `buf` back to the pool. Buffers are written to directly by ffmpeg, so a buffer that
+    /// doesn't have exactly `frame_size` bytes (e.g. one from a track with a different
+    /// resolution) is rejected instead of being handed out again. So is a buffer the pool has no
+    /// room for, which means it was recycled twice or doesn't come from the pool at all. Rejected
+    /// buffers are dropped.
+    pub fn recycle(&self, buf: Vec<u8>) -> Result<(), MediaError> {
+        if buf.len() != self.frame_size {
+            return Err(MediaError::BufferSizeMismatch {
+                expected: self.frame_size,
+                got: buf.len(),
+            });
+        }
+        return match self.shared.free_tx.try_send(buf) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(MediaError::PoolFull),
+            Err(TrySendError::Disconnected(_)) => Err(MediaError::ChannelClosed),
+        };
+    }
+
+    /// Another handle to the same pool, e.g. for a second worker decoding frames of the same size.
+    /// This is what `clone` does too, it is only spelled out here: the buffers aren't copied,
+    /// a buffer taken from one handle can be recycled into any other, and the memory is freed once
+    /// the last handle is dropped.
+    pub fn shared_clone(&self) -> FramePool<A> {
+        self.clone()
+    }
+
+    /// Whether there are other handles to this pool, e.g. the one of a track's worker.
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.shared) > 1
+    }
+
+    pub fn frame_size(&self) -> usize {
+        self.frame_size
+    }
+
+    /// Number of buffers the pool was created with.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Memory taken by all of the pool's buffers, wherever they currently are.
+    pub fn memory_usage_bytes(&self) -> usize {
+        self.capacity * self.frame_size
     }
 
-    pub fn recycle(&self, buf: Vec<u8>) -> Result<(), SendError<Vec<u8>>> {
-        return self.free_tx.send(buf);
+    /// Number of buffers currently sitting in the pool, i.e. neither held by the decoder nor by a
+    /// frame that hasn't been recycled yet. Both ends keep going while this is read, so it is only
+    /// a snapshot.
+    pub fn snapshot_free_count(&self) -> usize {
+        self.shared.free_rx.len()
     }
 }
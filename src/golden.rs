@@ -0,0 +1,199 @@
+use super::session::VideoFrame;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Checksum of the pixels of `frame`, for comparing frames without keeping them around. With the
+/// `xxhash` feature this is XXH3, which is stable. Otherwise it is the standard library's
+/// `DefaultHasher`, whose output may change between Rust releases, so golden files recorded with
+/// it may have to be re-recorded after a toolchain update.
+pub fn hash_frame(frame: &VideoFrame) -> u64 {
+    #[cfg(feature = "xxhash")]
+    {
+        xxhash_rust::xxh3::xxh3_64(&frame.data)
+    }
+    #[cfg(not(feature = "xxhash"))]
+    {
+        use std::hash::{DefaultHasher, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&frame.data);
+        hasher.finish()
+    }
+}
+
+/// The hashes of a sequence of frames, as decoded by a given version of ffmpeg. Record them once
+/// into a `.golden` file next to the test fixtures, then compare against them to catch pixel level
+/// regressions, e.g. after bumping ffmpeg.
+///
+/// The file has one `<pts> <hash>` line per frame, the pts being `-` for frames without one, so
+/// that diffs of it are readable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoldenFrameStore {
+    hashes: Vec<(Option<i64>, u64)>,
+}
+
+impl GoldenFrameStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid golden frame line: {line:?}"),
+            )
+        };
+
+        let mut hashes = Vec::new();
+        for line in fs::read_to_string(path)?.lines() {
+            let Some((pts, hash)) = line.split_once(' ') else {
+                return Err(invalid(line));
+            };
+            let pts = match pts {
+                "-" => None,
+                pts => Some(pts.parse().map_err(|_| invalid(line))?),
+            };
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| invalid(line))?;
+            hashes.push((pts, hash));
+        }
+        Ok(Self { hashes })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = String::new();
+        for (pts, hash) in &self.hashes {
+            match pts {
+                Some(pts) => write!(contents, "{pts}").unwrap(),
+                None => contents.push('-'),
+            }
+            writeln!(contents, " {hash:016x}").unwrap();
+        }
+        fs::write(path, contents)
+    }
+
+    /// Records the hash of `frame` after the ones recorded so far.
+    pub fn push(&mut self, frame: &VideoFrame) {
+        self.hashes.push((frame.pts, hash_frame(frame)));
+    }
+
+    pub fn hashes(&self) -> &[(Option<i64>, u64)] {
+        &self.hashes
+    }
+
+    /// Index of the first frame that differs between the two stores, if any. A store that has
+    /// more frames than the other differs at the first extra frame.
+    pub fn first_mismatch(&self, other: &GoldenFrameStore) -> Option<usize> {
+        let common = self.hashes.len().min(other.hashes.len());
+        let extra = (self.hashes.len() != other.hashes.len()).then_some(common);
+        self.hashes
+            .iter()
+            .zip(&other.hashes)
+            .position(|(a, b)| a != b)
+            .or(extra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::VideoFrameMetadata;
+    use ffmpeg_next as ffmpeg;
+
+    /// A frame one pixel wide, with one row per 4 bytes of `data`.
+    fn frame(data: &[u8], pts: Option<i64>) -> VideoFrame {
+        VideoFrame {
+            width: 1,
+            height: (data.len() / 4) as u32,
+            stride: 4,
+            data: data.to_vec(),
+            pts,
+            dts: pts,
+            pixel_format: ffmpeg::format::Pixel::RGBA,
+            sample_aspect_ratio: None,
+            hdr_metadata: None,
+            crop: None,
+            metadata: VideoFrameMetadata::default(),
+        }
+    }
+
+    fn store(frames: &[VideoFrame]) -> GoldenFrameStore {
+        let mut store = GoldenFrameStore::new();
+        for frame in frames {
+            store.push(frame);
+        }
+        store
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let store = store(&[frame(&[1, 2, 3, 4], Some(0)), frame(&[5, 6, 7, 8], None)]);
+        let path = std::env::temp_dir().join(format!("golden-{}.golden", std::process::id()));
+
+        store.save(&path).unwrap();
+        let loaded = GoldenFrameStore::load(&path);
+        fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.unwrap(), store);
+    }
+
+    #[test]
+    fn load_rejects_invalid_lines() {
+        let path = std::env::temp_dir().join(format!("invalid-{}.golden", std::process::id()));
+
+        fs::write(&path, "12 not-a-hash\n").unwrap();
+        let loaded = GoldenFrameStore::load(&path);
+        fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn first_mismatch_finds_the_first_differing_frame() {
+        let ab = store(&[frame(&[0; 4], Some(0)), frame(&[1; 4], Some(1))]);
+        let ac = store(&[frame(&[0; 4], Some(0)), frame(&[2; 4], Some(1))]);
+        // Same pixels, but not at the same pts.
+        let ab_moved = store(&[frame(&[0; 4], Some(0)), frame(&[1; 4], Some(5))]);
+        let a = store(&[frame(&[0; 4], Some(0))]);
+
+        assert_eq!(ab.first_mismatch(&ab.clone()), None);
+        assert_eq!(ab.first_mismatch(&ac), Some(1));
+        assert_eq!(ab.first_mismatch(&ab_moved), Some(1));
+        // Extra frames on either side count as a mismatch.
+        assert_eq!(a.first_mismatch(&ab), Some(1));
+        assert_eq!(ab.first_mismatch(&a), Some(1));
+        assert_eq!(GoldenFrameStore::new().first_mismatch(&a), Some(0));
+    }
+
+    #[test]
+    fn hash_depends_only_on_the_pixels() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            hash_frame(&frame(&data, Some(0))),
+            hash_frame(&frame(&data, Some(1)))
+        );
+        assert_ne!(
+            hash_frame(&frame(&data, None)),
+            hash_frame(&frame(&[0; 8], None))
+        );
+    }
+
+    #[cfg(feature = "xxhash")]
+    #[test]
+    fn hash_is_xxh3() {
+        // XXH3 of no bytes at all, as given by the reference implementation.
+        assert_eq!(hash_frame(&frame(&[], None)), 0x2d06800538d394c2);
+    }
+
+    #[cfg(not(feature = "xxhash"))]
+    #[test]
+    fn hash_is_stable_within_a_build() {
+        let data: Vec<u8> = (0..=255).collect();
+        let first = hash_frame(&frame(&data, None));
+        for _ in 0..4 {
+            assert_eq!(hash_frame(&frame(&data, None)), first);
+        }
+    }
+}
@@ -1,8 +1,31 @@
+mod audio_sink;
+mod config;
+mod debug_overlay;
 mod engine;
+mod error;
 mod frame_pool;
+mod golden;
+mod plugin;
+mod probe;
 mod session;
+mod stats;
 mod worker;
 
+pub use audio_sink::*;
+pub use config::*;
 pub use engine::*;
+pub use error::*;
 pub use frame_pool::*;
+pub use golden::*;
+pub use plugin::*;
+pub use probe::*;
 pub use session::*;
+pub use stats::*;
+
+/// The types most users need, so that a single `use bevy_ffmpeg::prelude::*;` gets you going.
+pub mod prelude {
+    pub use crate::{
+        AudioFrame, BevyFfmpegPlugin, FramePool, MediaEngine, MediaError, TrackConfig, TrackId,
+        TrackInfo, TrackState, VideoFrame,
+    };
+}
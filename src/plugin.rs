@@ -0,0 +1,69 @@
+use super::engine::MediaEngine;
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::intern::Interned;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::ecs::system::ResMut;
+#[cfg(feature = "thread_safe")]
+use {
+    super::engine::SharedMediaEngine, bevy::ecs::schedule::IntoScheduleConfigs,
+    bevy::ecs::schedule::common_conditions::resource_exists, bevy::ecs::system::Res,
+};
+
+/// Adds a `MediaEngine` resource to the app, along with a system that calls `MediaEngine::update`
+/// every frame. If the app already has a `MediaEngine` (e.g. one created with
+/// `MediaEngine::new_with_probe_cache`), that one is used instead.
+pub struct BevyFfmpegPlugin {
+    update_schedule: Interned<dyn ScheduleLabel>,
+}
+
+impl Default for BevyFfmpegPlugin {
+    fn default() -> Self {
+        Self {
+            update_schedule: Update.intern(),
+        }
+    }
+}
+
+impl BevyFfmpegPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the update system in `schedule` instead of `Update`, e.g. `FixedUpdate` for games that
+    /// tie everything to the physics tick, or `PostUpdate`.
+    pub fn with_update_schedule(mut self, schedule: impl ScheduleLabel + 'static) -> Self {
+        self.update_schedule = schedule.intern();
+        self
+    }
+}
+
+impl Plugin for BevyFfmpegPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world().contains_resource::<MediaEngine>() {
+            app.insert_resource(MediaEngine::new());
+        }
+        app.add_systems(self.update_schedule, media_engine_update);
+        #[cfg(feature = "thread_safe")]
+        app.add_systems(
+            self.update_schedule,
+            shared_media_engine_update.run_if(resource_exists::<SharedMediaEngine>),
+        );
+
+        #[cfg(feature = "bevy_reflect")]
+        app.register_type::<super::engine::TrackId>()
+            .register_type::<super::engine::TrackState>()
+            .register_type::<super::probe::TrackInfo>()
+            .register_type::<super::stats::PlaybackStats>();
+    }
+}
+
+/// Pulls the messages of every track's worker. See `MediaEngine::update`.
+pub fn media_engine_update(mut engine: ResMut<MediaEngine>) {
+    engine.update();
+}
+
+/// Same as `media_engine_update`, for the engine behind a `SharedMediaEngine`.
+#[cfg(feature = "thread_safe")]
+pub fn shared_media_engine_update(engine: Res<SharedMediaEngine>) {
+    engine.lock().update();
+}
@@ -0,0 +1,90 @@
+use super::config::TrackSource;
+use super::error::MediaError;
+use super::session::{attached_picture, stream_count};
+use ffmpeg_next as ffmpeg;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// What we know about a media file without decoding any of it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "bevy_reflect", derive(bevy::reflect::Reflect))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackInfo {
+    /// Name of the container format as reported by ffmpeg, e.g. `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub format_name: String,
+    /// Duration in seconds, if the container knows it.
+    pub duration: Option<f64>,
+    /// Size of the best video stream, if there is one.
+    pub size: Option<(u32, u32)>,
+    pub has_video: bool,
+    pub has_audio: bool,
+    /// Encoded image (usually JPEG or PNG) attached to the file, e.g. album art.
+    pub attached_picture: Option<Vec<u8>>,
+    /// Start and end of the media in seconds, see `pts_range_in_seconds`. This takes decoding the
+    /// first frame, so it is only known for tracks, not when probing.
+    pub time_range: Option<(f64, f64)>,
+}
+
+/// Opens `source` and reads its headers to find out what it contains. Nothing is decoded, so this
+/// is much cheaper than loading a track, but it is still blocking IO.
+pub fn probe_media(source: &TrackSource) -> Result<TrackInfo, MediaError> {
+    ffmpeg::init()?;
+    let input_format_ctx = ffmpeg::format::input(&source.url())?;
+    input_info(&input_format_ctx)
+}
+
+/// Same as `probe_media`, but for an input that is already open.
+pub(crate) fn input_info(
+    input_format_ctx: &ffmpeg::format::context::Input,
+) -> Result<TrackInfo, MediaError> {
+    let duration = match input_format_ctx.duration() {
+        d if d == ffmpeg::ffi::AV_NOPTS_VALUE || d < 0 => None,
+        d => Some(d as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE)),
+    };
+
+    let size = match input_format_ctx.streams().best(ffmpeg::media::Type::Video) {
+        Some(stream) => {
+            let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+            let decoder = context.decoder().video()?;
+            Some((decoder.width(), decoder.height()))
+        }
+        None => None,
+    };
+
+    Ok(TrackInfo {
+        format_name: input_format_ctx.format().name().to_string(),
+        duration,
+        size,
+        has_video: stream_count(input_format_ctx, ffmpeg::media::Type::Video) > 0,
+        has_audio: stream_count(input_format_ctx, ffmpeg::media::Type::Audio) > 0,
+        attached_picture: attached_picture(input_format_ctx),
+        time_range: None,
+    })
+}
+
+/// Remembers the result of `probe_media` for every path, so that probing the same file again (e.g.
+/// when a playlist repeats an entry) doesn't re-open it. This lives in memory only.
+#[derive(Default)]
+pub struct ProbeCache(HashMap<String, Arc<TrackInfo>>);
+
+impl ProbeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_probe(&mut self, path: &str) -> Result<Arc<TrackInfo>, MediaError> {
+        if let Some(info) = self.0.get(path) {
+            return Ok(info.clone());
+        }
+
+        let info = Arc::new(probe_media(&TrackSource::from(path))?);
+        self.0.insert(path.to_string(), info.clone());
+        Ok(info)
+    }
+
+    /// Forgets about `path` so that the next `get_or_probe` opens the file again, e.g. because
+    /// it changed on disk.
+    pub fn invalidate(&mut self, path: &str) {
+        self.0.remove(path);
+    }
+}
@@ -1,13 +1,277 @@
+use super::config::{DecodeErrorPolicy, TrackSource};
+use super::error::MediaError;
 use super::frame_pool::FramePool;
+#[cfg(feature = "bevy_render")]
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use ffmpeg::rescale::Rescale;
 use ffmpeg_next as ffmpeg;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
 use std::ptr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VideoFrame {
     pub width: u32,
     pub height: u32,
+    /// Number of bytes from the start of a row to the start of the next one. Rows are packed, i.e.
+    /// this is `width * bytes_per_pixel`, unless `SessionOptions::stride_alignment` pads them.
+    pub stride: u32,
     pub data: Vec<u8>,
     pub pts: Option<i64>,
+    /// Decode timestamp of the packet the frame came from. With B-frames, frames come out in
+    /// presentation order, which is not the order they were decoded in.
+    pub dts: Option<i64>,
+    /// `RGBA` (or `BGRA` if `SessionOptions::bgra_output` is set) or, for high bit depth videos,
+    /// `RGBA64LE`.
+    pub pixel_format: ffmpeg::format::Pixel,
+    /// Shape of a single pixel (SAR), when the stream specifies one. Anamorphic videos, e.g. most
+    /// DVDs, have non-square pixels and have to be stretched horizontally by this ratio.
+    pub sample_aspect_ratio: Option<ffmpeg::Rational>,
+    /// HDR10 metadata attached to the frame, if any. Renderers need this to tone map properly.
+    pub hdr_metadata: Option<HdrMetadata>,
+    /// The borders the decoded frame had outside of its active area, if any. Only the active area
+    /// is converted, stretched to `width` x `height` like filter graph outputs are. Decoders
+    /// usually crop frames themselves, in which case this is `None`.
+    pub crop: Option<CropRect>,
+    pub metadata: VideoFrameMetadata,
+}
+
+/// Number of pixels to remove from each side of a decoded frame to get its active area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropRect {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+/// Extra information about a frame that isn't needed to display it, mostly useful for monitoring.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct VideoFrameMetadata {
+    /// Bitrate of the last packet sent to the decoder before this frame came out, in kbit/s. With
+    /// B-frames, this is not necessarily the packet the frame was decoded from.
+    pub bitrate_kbps: Option<f32>,
+    /// Whether the frame came without a pts and we made one up, see `VideoState::last_pts`.
+    pub pts_synthesized: bool,
+}
+
+impl VideoFrame {
+    /// Width the frame should be displayed at to account for non-square pixels. This is just
+    /// `width` when there is no sample aspect ratio.
+    pub fn display_width(&self) -> u32 {
+        match self.sample_aspect_ratio {
+            Some(sar) => {
+                (self.width as u64 * sar.numerator() as u64 / sar.denominator() as u64) as u32
+            }
+            None => self.width,
+        }
+    }
+
+    /// Copies the frame into an `image::RgbaImage`. High bit depth frames are truncated to 8 bits
+    /// per component, since `RgbaImage` can't hold anything more.
+    #[cfg(feature = "image")]
+    pub fn copy_as_image(&self) -> image::RgbaImage {
+        let data = match self.pixel_format {
+            // Little endian, so the most significant byte of every component comes second.
+            ffmpeg::format::Pixel::RGBA64LE => self
+                .rows()
+                .flat_map(|row| row.chunks_exact(2))
+                .map(|c| c[1])
+                .collect(),
+            ffmpeg::format::Pixel::BGRA => self
+                .rows()
+                .flat_map(|row| row.chunks_exact(4))
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect(),
+            _ => self.rows().flatten().copied().collect(),
+        };
+        image::RgbaImage::from_raw(self.width, self.height, data).unwrap()
+    }
+
+    /// Views the pixels as a slice of `T` without copying them, e.g. `[u8; 4]` for `RGBA` frames.
+    /// Padding at the end of the rows, if any (see `stride`), is part of the slice.
+    /// This panics if the size of `data` isn't a multiple of the size of `T`, or if `T` needs a
+    /// stricter alignment than `data` has. `data` is a `Vec<u8>`, so its alignment isn't
+    /// guaranteed: types like `[u16; 4]` for `RGBA64LE` frames work with the usual allocators,
+    /// but arrays of bytes are the only ones that always do.
+    #[cfg(feature = "bytemuck")]
+    pub fn data_as_typed<T: bytemuck::Pod>(&self) -> &[T] {
+        bytemuck::cast_slice(&self.data)
+    }
+
+    /// Copies a single component out of every pixel, `channel` being 0 for red, 1 for green, 2 for
+    /// blue and 3 for alpha whatever the order of `data` is. High bit depth frames are truncated to
+    /// 8 bits per component, like `copy_as_image` does. Returns `None` if `channel` is not below 4.
+    pub fn copy_channel(&self, channel: usize) -> Option<Vec<u8>> {
+        if channel >= 4 {
+            return None;
+        }
+        let channel = match self.pixel_format {
+            // Blue and red are swapped.
+            ffmpeg::format::Pixel::BGRA if channel < 3 => 2 - channel,
+            _ => channel,
+        };
+        let bytes_per_component = bytes_per_pixel(self.pixel_format) as usize / 4;
+        // Little endian, so the most significant byte of a 16-bit component comes last.
+        let offset = channel * bytes_per_component + bytes_per_component - 1;
+        let data = self
+            .rows()
+            .flat_map(|row| row.chunks_exact(bytes_per_component * 4))
+            .map(|pixel| pixel[offset])
+            .collect();
+        Some(data)
+    }
+
+    /// The pixel at column `x` and row `y`, as `[r, g, b, a]` whatever the order of `data` is. High
+    /// bit depth frames are truncated to 8 bits per component, like `copy_as_image` does. Returns
+    /// `None` if the pixel is outside of the frame.
+    pub fn pixel_at(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let bytes_per_pixel = bytes_per_pixel(self.pixel_format) as usize;
+        let offset = y as usize * self.stride as usize + x as usize * bytes_per_pixel;
+        let pixel = self.data.get(offset..offset + bytes_per_pixel)?;
+        Some(match self.pixel_format {
+            // Little endian, so the most significant byte of every component comes second.
+            ffmpeg::format::Pixel::RGBA64LE => [pixel[1], pixel[3], pixel[5], pixel[7]],
+            ffmpeg::format::Pixel::BGRA => [pixel[2], pixel[1], pixel[0], pixel[3]],
+            _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+        })
+    }
+
+    /// The rows of the frame, without the padding `stride` may add at their end.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let row_size = (self.width * bytes_per_pixel(self.pixel_format)) as usize;
+        self.data
+            .chunks_exact(self.stride as usize)
+            .take(self.height as usize)
+            .map(move |row| &row[..row_size])
+    }
+
+    /// The texture format matching `pixel_format`, so that `data` can be uploaded as is.
+    #[cfg(feature = "bevy_render")]
+    pub fn texture_format(&self) -> TextureFormat {
+        match self.pixel_format {
+            ffmpeg::format::Pixel::RGBA64LE => TextureFormat::Rgba16Unorm,
+            ffmpeg::format::Pixel::BGRA => TextureFormat::Bgra8UnormSrgb,
+            _ => TextureFormat::Rgba8UnormSrgb,
+        }
+    }
+
+    /// Describes a texture the frame can be uploaded to as is, e.g. to set up an `Image`'s
+    /// `texture_descriptor`. Every frame of a track has the same size and format, so this can be
+    /// done once with the first frame. If the rows are padded (see `stride`), the upload has to
+    /// use `stride` as its `bytes_per_row`.
+    #[cfg(feature = "bevy_render")]
+    pub fn bevy_texture_descriptor(
+        &self,
+    ) -> TextureDescriptor<Option<&'static str>, &'static [TextureFormat]> {
+        TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.texture_format(),
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        }
+    }
+}
+
+/// Takes the frame's buffer over rather than copying it like `copy_as_image` does, so recycling
+/// the frame is not an option afterwards. `BGRA` frames are swizzled in place, and high bit depth
+/// frames become 16-bit images, which does take a copy.
+#[cfg(feature = "image")]
+impl From<VideoFrame> for image::DynamicImage {
+    fn from(frame: VideoFrame) -> Self {
+        let (width, height) = (frame.width, frame.height);
+        let row_size = (width * bytes_per_pixel(frame.pixel_format)) as usize;
+        let mut data = frame.data;
+        if frame.stride as usize != row_size {
+            // The rows only move towards the start of the buffer, so they can be packed in place.
+            for row in 0..height as usize {
+                let start = row * frame.stride as usize;
+                data.copy_within(start..start + row_size, row * row_size);
+            }
+            data.truncate(row_size * height as usize);
+        }
+        match frame.pixel_format {
+            ffmpeg::format::Pixel::RGBA64LE => {
+                let data = data
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                image::DynamicImage::ImageRgba16(
+                    image::ImageBuffer::from_raw(width, height, data).unwrap(),
+                )
+            }
+            format => {
+                if format == ffmpeg::format::Pixel::BGRA {
+                    for pixel in data.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                }
+                image::DynamicImage::ImageRgba8(
+                    image::RgbaImage::from_raw(width, height, data).unwrap(),
+                )
+            }
+        }
+    }
+}
+
+/// Frames are considered equal when they have the same pts and size. Comparing the pixels would
+/// turn every `==` into an O(n) operation, so that is left to `frame_data_matches`.
+impl PartialEq for VideoFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.pts == other.pts && (self.width, self.height) == (other.width, other.height)
+    }
+}
+
+impl Eq for VideoFrame {}
+
+/// Whether `a` and `b` are equal and have exactly the same pixels.
+pub fn frame_data_matches(a: &VideoFrame, b: &VideoFrame) -> bool {
+    a == b && a.data == b.data
+}
+
+/// The crop `frame` asks for, if it asks for any.
+fn crop_rect(frame: &ffmpeg::util::frame::Video) -> Option<CropRect> {
+    // ffmpeg-next doesn't expose the crop fields.
+    let crop = unsafe {
+        let frame = frame.as_ptr();
+        CropRect {
+            top: (*frame).crop_top as u32,
+            bottom: (*frame).crop_bottom as u32,
+            left: (*frame).crop_left as u32,
+            right: (*frame).crop_right as u32,
+        }
+    };
+    (crop.top > 0 || crop.bottom > 0 || crop.left > 0 || crop.right > 0).then_some(crop)
+}
+
+/// Static HDR metadata as defined by SMPTE ST 2086 (mastering display) and CTA-861.3 (content
+/// light level). Any part the stream doesn't provide is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HdrMetadata {
+    /// MaxCLL, in cd/m².
+    pub max_content_light_level: Option<u32>,
+    /// MaxFALL, in cd/m².
+    pub max_frame_average_light_level: Option<u32>,
+    /// CIE 1931 xy chromaticity coordinates of the red, green and blue primaries of the mastering
+    /// display.
+    pub display_primaries: Option<[[f64; 2]; 3]>,
+    /// CIE 1931 xy chromaticity coordinates of the mastering display's white point.
+    pub white_point: Option<[f64; 2]>,
+    /// Minimum and maximum luminance of the mastering display, in cd/m².
+    pub luminance_range: Option<(f64, f64)>,
 }
 
 pub struct VideoState {
@@ -15,21 +279,199 @@ pub struct VideoState {
     pub decoder: ffmpeg::decoder::Video,
     pub scaler: ffmpeg::software::scaling::Context,
     pub decoded: ffmpeg::util::frame::Video,
+    /// Optional filter graph decoded frames go through before they are scaled, along with the
+    /// frame its output is read into.
+    pub filter_graph: Option<ffmpeg::filter::Graph>,
+    pub filtered: ffmpeg::util::frame::Video,
 
     pub width: u32,
     pub height: u32,
+    /// Bits per color component of the decoded frames. Anything above 8 (10-bit and 12-bit HDR
+    /// content) is converted into `RGBA64LE` instead of `RGBA` so that we don't lose precision.
+    pub bit_depth: u8,
+    pub output_format: ffmpeg::format::Pixel,
+    /// Bytes per row of the frames we output, see `VideoFrame::stride`.
+    pub stride: u32,
+    /// Scales frames down to half their resolution when `SessionOptions::subsampled_output` is
+    /// set, along with the stride of the frames it outputs.
+    pub subsampler: Option<ffmpeg::software::scaling::Context>,
+    pub subsampled_stride: u32,
+    /// Matrix the scalers convert YUV into RGB with, see `set_color_matrix`. `None` leaves it up to
+    /// libswscale, which goes with BT.601.
+    pub color_matrix: Option<ColorMatrix>,
+
+    pub time_base: ffmpeg::Rational,
+    /// The start time the stream declares. This can be `AV_NOPTS_VALUE`, and even when it isn't,
+    /// it doesn't necessarily match the pts of the first frame, so prefer `first_pts`.
+    pub start_pts: i64,
+    /// Pts of the first decoded frame. Everything that is expressed in seconds is relative to it.
+    pub first_pts: i64,
+
+    pub decode_error_policy: DecodeErrorPolicy,
+    /// Number of packets in a row that failed to decode.
+    pub consecutive_errors: u32,
+
+    /// Set after a precise seek. Decoded frames with a pts lower than this are dropped without
+    /// being scaled.
+    pub skip_until_pts: Option<i64>,
+    /// Set while `flush_until` drains the decoder. Decoded frames with a pts higher than this are
+    /// dropped without being scaled.
+    pub stop_after_pts: Option<i64>,
+
+    /// Bitrate of the last packet sent to the decoder, in bits per second.
+    pub last_packet_bitrate: Option<f64>,
+    /// Pts of the last decoded frame. Frames that come without a pts (which happens with MPEG-TS
+    /// and some MP4 files) get the dts of their packet instead, or if there is none either, this
+    /// plus `frame_duration`.
+    pub last_pts: Option<i64>,
+    /// Duration of a frame according to the stream's average frame rate, in `time_base`.
+    pub frame_duration: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamType {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Unknown,
+}
+
+impl From<ffmpeg::media::Type> for StreamType {
+    fn from(medium: ffmpeg::media::Type) -> Self {
+        match medium {
+            ffmpeg::media::Type::Video => StreamType::Video,
+            ffmpeg::media::Type::Audio => StreamType::Audio,
+            ffmpeg::media::Type::Subtitle => StreamType::Subtitle,
+            ffmpeg::media::Type::Data => StreamType::Data,
+            ffmpeg::media::Type::Attachment => StreamType::Attachment,
+            ffmpeg::media::Type::Unknown => StreamType::Unknown,
+        }
+    }
+}
 
+/// Describes one of the streams in the container, regardless of whether we decode it or not.
+#[derive(Debug, Clone)]
+pub struct StreamDescriptor {
+    pub index: usize,
+    pub stream_type: StreamType,
+    /// The `language` metadata tag of the stream, usually an ISO 639-2 code like `eng`.
+    pub language: Option<String>,
+    pub codec_name: String,
+}
+
+/// A single subtitle, timed in the video stream's time base so that it can be compared with the
+/// pts of video frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtitleFrame {
+    pub start_pts: i64,
+    pub end_pts: i64,
+    /// The text of the subtitle, without any ASS styling. Bitmap subtitles (DVD, PGS) are not
+    /// supported.
+    pub text: String,
+}
+
+/// A chunk of decoded audio, converted into interleaved 32-bit float samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFrame {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// One sample per channel after the other, e.g. `LRLRLR...` for stereo.
+    pub samples: Vec<f32>,
+    /// In the audio stream's time base.
+    pub pts: Option<i64>,
+    /// Root mean square of `samples`, over all channels, i.e. how loud this chunk is on a linear
+    /// scale from 0 to 1. `20 * rms.log10()` gives it in dBFS.
+    pub rms: f32,
+}
+
+pub struct AudioState {
+    pub stream_index: usize,
+    pub decoder: ffmpeg::decoder::Audio,
+    /// Converts decoded frames into packed `f32`. It is only created once we get the first frame,
+    /// since the decoder doesn't always know the sample format and channel layout before that.
+    pub resampler: Option<ffmpeg::software::resampling::Context>,
+    /// Passed to the resampler when it is created, see `SessionOptions::swr_options`.
+    pub resampler_options: HashMap<String, String>,
+    pub decoded: ffmpeg::util::frame::Audio,
     pub time_base: ffmpeg::Rational,
+    /// The start time the stream declares, or 0 if it doesn't. Positions in seconds are relative
+    /// to it when there is no video stream.
     pub start_pts: i64,
+    /// Added to the pts of every `AudioFrame`, in `time_base`. Positive values delay the audio,
+    /// e.g. to make up for a TV that takes longer to show the picture than to play the sound.
+    pub audio_offset_pts: i64,
+    /// RMS of the last chunk of audio resampled, see `AudioFrame::rms`.
+    pub last_rms: f32,
+}
+
+/// Converts an audio delay in seconds into `AudioState::audio_offset_pts`.
+pub fn audio_delay_to_pts(audio: &AudioState, seconds: f64) -> i64 {
+    let microseconds = (seconds * 1_000_000.0) as i64;
+    microseconds.rescale(ffmpeg::rescale::TIME_BASE, audio.time_base)
+}
+
+pub struct SubtitleState {
+    pub stream_index: usize,
+    pub decoder: ffmpeg::decoder::Subtitle,
+    pub time_base: ffmpeg::Rational,
 }
 
 pub struct MediaSession {
     pub input_format_ctx: ffmpeg::format::context::Input,
     pub video: Option<VideoState>,
+    pub audio: Option<AudioState>,
+    pub subtitle: Option<SubtitleState>,
+    pub streams: Vec<StreamDescriptor>,
+    /// Packets that were read from the input but not handed out by `read_packet` yet.
+    pub pending_packets: VecDeque<ffmpeg::Packet>,
+    /// Encoded image (usually JPEG or PNG) attached to the media, e.g. the album art of an MP3.
+    pub attached_picture: Option<Vec<u8>>,
 }
 
 pub enum ProcessOutput {
     Video(VideoFrame),
+    /// Half resolution version of the `Video` frame right before it, see
+    /// `SessionOptions::subsampled_output`.
+    VideoSubsampled(VideoFrame),
+    Audio(AudioFrame),
+    Subtitle(SubtitleFrame),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekMode {
+    /// Seeks to the keyframe before the target and decodes (but drops) every frame up to the
+    /// target. Slowest, but the first frame out of the decoder is exactly at the target.
+    Precise,
+    /// Seeks to the closest keyframe at or before the target.
+    KeyframeBackward,
+    /// Seeks to the closest keyframe at or after the target.
+    KeyframeForward,
+}
+
+/// Standard a video's YUV is converted into RGB with, see `set_color_matrix`. Each one has its own
+/// weights for the color components, so picking the wrong one shifts the colors slightly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMatrix {
+    /// SD video (ITU-R BT.601).
+    Bt601,
+    /// HD video (ITU-R BT.709).
+    Bt709,
+    /// UHD and HDR video (ITU-R BT.2020, non-constant luminance).
+    Bt2020,
+}
+
+impl ColorMatrix {
+    /// The matching `SWS_CS_*` constant of libswscale.
+    fn sws_colorspace(self) -> i32 {
+        let colorspace = match self {
+            ColorMatrix::Bt601 => ffmpeg::ffi::SWS_CS_ITU601,
+            ColorMatrix::Bt709 => ffmpeg::ffi::SWS_CS_ITU709,
+            ColorMatrix::Bt2020 => ffmpeg::ffi::SWS_CS_BT2020,
+        };
+        colorspace as i32
+    }
 }
 
 pub enum Packet {
@@ -37,6 +479,97 @@ pub enum Packet {
     Eof,
 }
 
+fn rational_to_f64(rational: ffmpeg::ffi::AVRational) -> f64 {
+    if rational.den == 0 {
+        return 0.0;
+    }
+    rational.num as f64 / rational.den as f64
+}
+
+/// Reads the mastering display and content light level side data of `frame`. Returns `None` if
+/// the frame carries neither.
+fn hdr_metadata(frame: &ffmpeg::util::frame::Video) -> Option<HdrMetadata> {
+    use ffmpeg::util::frame::side_data::Type;
+
+    let mut metadata = HdrMetadata::default();
+    let mut found = false;
+
+    if let Some(side_data) = frame.side_data(Type::MasteringDisplayMetadata) {
+        let data = side_data.data();
+        if data.len() >= size_of::<ffmpeg::ffi::AVMasteringDisplayMetadata>() {
+            found = true;
+            // The side data buffer is allocated by ffmpeg for exactly this struct, so it is
+            // properly aligned.
+            let mastering =
+                unsafe { &*(data.as_ptr() as *const ffmpeg::ffi::AVMasteringDisplayMetadata) };
+            if mastering.has_primaries != 0 {
+                metadata.display_primaries = Some(
+                    mastering
+                        .display_primaries
+                        .map(|[x, y]| [rational_to_f64(x), rational_to_f64(y)]),
+                );
+                metadata.white_point = Some(mastering.white_point.map(rational_to_f64));
+            }
+            if mastering.has_luminance != 0 {
+                metadata.luminance_range = Some((
+                    rational_to_f64(mastering.min_luminance),
+                    rational_to_f64(mastering.max_luminance),
+                ));
+            }
+        }
+    }
+
+    if let Some(side_data) = frame.side_data(Type::ContentLightLevel) {
+        let data = side_data.data();
+        if data.len() >= size_of::<ffmpeg::ffi::AVContentLightMetadata>() {
+            found = true;
+            let light = unsafe { &*(data.as_ptr() as *const ffmpeg::ffi::AVContentLightMetadata) };
+            metadata.max_content_light_level = Some(light.MaxCLL);
+            metadata.max_frame_average_light_level = Some(light.MaxFALL);
+        }
+    }
+
+    found.then_some(metadata)
+}
+
+/// Number of frames the video decoder may hold on to before handing any out: the frames it reorders
+/// (B-frames) plus up to one per thread with frame threading. They can all come out at once, e.g.
+/// when the decoder is flushed at the end of the stream, and each of them needs a buffer of the
+/// pool before any of them reaches the consumer.
+pub fn decoder_frame_delay(video: &VideoState) -> usize {
+    let (reorder, threads) = unsafe {
+        let context = video.decoder.as_ptr();
+        ((*context).has_b_frames, (*context).thread_count)
+    };
+    reorder.max(0) as usize + threads.max(1) as usize
+}
+
+/// Number of bits per color component of `format`, e.g. 8 for `YUV420P` or 10 for `P010LE`.
+fn pixel_bit_depth(format: ffmpeg::format::Pixel) -> u8 {
+    match format.descriptor() {
+        Some(descriptor) => unsafe { (*descriptor.as_ptr()).comp[0].depth as u8 },
+        None => 8,
+    }
+}
+
+/// Number of bytes a single pixel takes in one of the formats we convert frames into.
+pub fn bytes_per_pixel(format: ffmpeg::format::Pixel) -> u32 {
+    match format {
+        ffmpeg::format::Pixel::RGBA64LE => 8,
+        _ => 4,
+    }
+}
+
+/// Bitrate of `packet` in bits per second, computed from its size and duration. Returns `None` when
+/// the packet has no duration, which some demuxers don't bother to set.
+pub fn get_packet_bitrate(packet: &ffmpeg::Packet, time_base: ffmpeg::Rational) -> Option<f64> {
+    let duration_secs = packet.duration() as f64 * f64::from(time_base);
+    if duration_secs <= 0.0 {
+        return None;
+    }
+    Some(packet.size() as f64 * 8.0 / duration_secs)
+}
+
 /// This unsafe code seems to be unavoidable unfortunately. ffmpeg-next is
 /// awesome and tries to keep things as safe as possible, but unfortunately, it
 /// also puts limit to the performance to some extent. There seems to be two
@@ -49,17 +582,41 @@ pub enum Packet {
 ///    your buffer directly so that the scaler can directly write to it and you
 ///    do not have any allocation or copying.
 /// We'll go with 2 :D
+///
+/// Whatever writes into the frame trusts its pointers and strides, so this fails with `EINVAL`
+/// if `buffer` can't hold `height` rows of `stride` bytes (or all the planes of a planar format),
+/// or if `stride` is too short for a row.
 fn create_video_frame_from_buffer(
     width: u32,
     height: u32,
+    stride: u32,
     format: ffmpeg::format::Pixel,
-    buffer: &mut Vec<u8>,
-) -> ffmpeg::util::frame::Video {
+    buffer: &mut [u8],
+) -> Result<ffmpeg::util::frame::Video, ffmpeg::Error> {
+    let invalid = ffmpeg::Error::Other {
+        errno: ffmpeg::util::error::EINVAL,
+    };
     let mut frame = ffmpeg::util::frame::Video::empty();
     frame.set_width(width);
     frame.set_height(height);
     frame.set_format(format);
 
+    if is_planar(format) {
+        if frame_buffer_size(width, height, format).is_none_or(|size| size > buffer.len()) {
+            return Err(invalid);
+        }
+        unsafe { setup_planar_frame(frame.as_mut_ptr(), buffer, width, height, format)? };
+        return Ok(frame);
+    }
+
+    let fits = min_stride(width, format).is_some_and(|min_stride| stride >= min_stride)
+        && (stride as usize)
+            .checked_mul(height as usize)
+            .is_some_and(|size| size <= buffer.len());
+    if !fits {
+        return Err(invalid);
+    }
+
     unsafe {
         let frame_ptr = frame.as_mut_ptr();
 
@@ -68,61 +625,532 @@ fn create_video_frame_from_buffer(
         (*frame_ptr).data[2] = ptr::null_mut();
         (*frame_ptr).data[3] = ptr::null_mut();
 
-        (*frame_ptr).linesize[0] = (width * 4) as i32;
+        (*frame_ptr).linesize[0] = stride as i32;
         (*frame_ptr).linesize[1] = 0;
         (*frame_ptr).linesize[2] = 0;
         (*frame_ptr).linesize[3] = 0;
     }
 
-    frame
+    Ok(frame)
 }
 
-pub fn load_media_session(source: &str) -> Result<MediaSession, ffmpeg::Error> {
+/// Number of bytes a row of `width` pixels takes in `format` without any padding, or in its first
+/// plane for planar formats. `None` if ffmpeg doesn't know the format or the row is too long.
+fn min_stride(width: u32, format: ffmpeg::format::Pixel) -> Option<u32> {
+    let width = i32::try_from(width).ok()?;
+    let mut linesizes = [0i32; 4];
+    let ret = unsafe {
+        ffmpeg::ffi::av_image_fill_linesizes(linesizes.as_mut_ptr(), format.into(), width)
+    };
+    if ret < 0 {
+        return None;
+    }
+    u32::try_from(linesizes[0])
+        .ok()
+        .filter(|&linesize| linesize > 0)
+}
+
+/// Whether `format` stores its components in separate planes, e.g. `YUV420P`.
+fn is_planar(format: ffmpeg::format::Pixel) -> bool {
+    match format.descriptor() {
+        Some(descriptor) => unsafe {
+            (*descriptor.as_ptr()).flags & ffmpeg::ffi::AV_PIX_FMT_FLAG_PLANAR as u64 != 0
+        },
+        None => false,
+    }
+}
+
+/// Number of bytes a row of `width` pixels takes once padded to a multiple of `alignment`, see
+/// `SessionOptions::stride_alignment`. Only makes sense for the packed formats we output.
+pub fn aligned_stride(width: u32, format: ffmpeg::format::Pixel, alignment: usize) -> u32 {
+    align_up((width * bytes_per_pixel(format)) as usize, alignment) as u32
+}
+
+/// Rounds `value` up to the next multiple of `alignment`. An `alignment` of 0 is treated as 1.
+fn align_up(value: usize, alignment: usize) -> usize {
+    value.next_multiple_of(alignment.max(1))
+}
+
+/// Number of bytes a `width` x `height` frame in `format` takes once its rows and planes are laid
+/// out one after the other without any padding, which is how we lay them out in our buffers.
+/// `None` if ffmpeg doesn't know the format, or if the frame is too large for it.
+fn frame_buffer_size(width: u32, height: u32, format: ffmpeg::format::Pixel) -> Option<usize> {
+    let (width, height) = (i32::try_from(width).ok()?, i32::try_from(height).ok()?);
+    let size = unsafe { ffmpeg::ffi::av_image_get_buffer_size(format.into(), width, height, 1) };
+    usize::try_from(size).ok()
+}
+
+/// Same as the packed case of `create_video_frame_from_buffer`, but for planar formats, which need
+/// a pointer and a stride per plane. With `YUV420P` for instance, the `U` and `V` planes are a
+/// quarter of the size of the `Y` plane and come right after it. ffmpeg works the offsets and
+/// strides out for us.
+///
+/// `buffer` must hold at least `frame_buffer_size(width, height, format)` bytes, and `frame_ptr`
+/// must point to a valid frame. Fails if ffmpeg doesn't know the format or the size is invalid, in
+/// which case the frame's pointers are left alone.
+unsafe fn setup_planar_frame(
+    frame_ptr: *mut ffmpeg::ffi::AVFrame,
+    buffer: &mut [u8],
+    width: u32,
+    height: u32,
+    format: ffmpeg::format::Pixel,
+) -> Result<(), ffmpeg::Error> {
+    let mut data = [ptr::null_mut(); 4];
+    let mut linesize = [0i32; 4];
+    let ret = unsafe {
+        ffmpeg::ffi::av_image_fill_arrays(
+            data.as_mut_ptr(),
+            linesize.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            format.into(),
+            width as i32,
+            height as i32,
+            1,
+        )
+    };
+    if ret < 0 {
+        return Err(ffmpeg::Error::from(ret));
+    }
+    unsafe {
+        (*frame_ptr).data[..4].copy_from_slice(&data);
+        (*frame_ptr).linesize[..4].copy_from_slice(&linesize);
+    }
+    Ok(())
+}
+
+/// An ffmpeg frame whose data is a buffer it borrows, see `try_create_video_frame_from_buffer`. It
+/// can only be written to through `scale_from`, which checks that what is written matches the
+/// frame, so nothing can point it outside of the buffer.
+pub struct BufferVideoFrame<'a> {
+    frame: ffmpeg::util::frame::Video,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+impl BufferVideoFrame<'_> {
+    pub fn frame(&self) -> &ffmpeg::util::frame::Video {
+        &self.frame
+    }
+
+    /// Converts `input` into the frame, and so into the buffer. The scaler's output has to have
+    /// the frame's size and format.
+    pub fn scale_from(
+        &mut self,
+        scaler: &mut ffmpeg::software::scaling::Context,
+        input: &ffmpeg::util::frame::Video,
+    ) -> Result<(), MediaError> {
+        Ok(scaler.run(input, &mut self.frame)?)
+    }
+}
+
+/// Same as `create_video_frame_from_buffer`, which is what the decoder uses with the buffers of its
+/// `FramePool`, but checks that `buffer` is big enough for a `width` x `height` frame in `format`
+/// first, rows and planes packed. ffmpeg would happily write past its end otherwise. Formats
+/// ffmpeg doesn't know and sizes too large for it fail with `BufferTooSmall` too, with a
+/// `required` size of `usize::MAX`.
+pub fn try_create_video_frame_from_buffer(
+    width: u32,
+    height: u32,
+    format: ffmpeg::format::Pixel,
+    buffer: &mut [u8],
+) -> Result<BufferVideoFrame<'_>, MediaError> {
+    let too_small = |required| MediaError::BufferTooSmall {
+        required,
+        got: buffer.len(),
+    };
+    let layout = frame_buffer_size(width, height, format).zip(min_stride(width, format));
+    let Some((required, stride)) = layout else {
+        return Err(too_small(usize::MAX));
+    };
+    if buffer.len() < required {
+        return Err(too_small(required));
+    }
+    let frame = create_video_frame_from_buffer(width, height, stride, format, buffer)?;
+    Ok(BufferVideoFrame {
+        frame,
+        _buffer: PhantomData,
+    })
+}
+
+pub fn load_media_session(source: &TrackSource) -> Result<MediaSession, MediaError> {
+    load_media_session_with_opts(source, &HashMap::new())
+}
+
+/// Same as `load_media_session`, but `opts` are handed over to ffmpeg when the input is opened.
+/// These are demuxer and protocol options, e.g. `rtsp_transport=tcp`, `reconnect=1` or
+/// `timeout=5000000`. Options ffmpeg doesn't know about are silently ignored.
+pub fn load_media_session_with_opts(
+    source: &TrackSource,
+    opts: &HashMap<String, String>,
+) -> Result<MediaSession, MediaError> {
+    let options = SessionOptions {
+        format_options: opts.clone(),
+        ..Default::default()
+    };
+    load_session(source, &options)
+}
+
+/// Same as `load_media_session_with_opts`, but any video stream is ignored, see
+/// `SessionOptions::audio_only`.
+pub fn load_audio_only_session_with_opts(
+    source: &TrackSource,
+    opts: &HashMap<String, String>,
+) -> Result<MediaSession, MediaError> {
+    let options = SessionOptions {
+        format_options: opts.clone(),
+        audio_only: true,
+        ..Default::default()
+    };
+    load_session(source, &options)
+}
+
+/// Everything that can be tweaked when a session is opened.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    /// Demuxer and protocol options, see `load_media_session_with_opts`.
+    pub format_options: HashMap<String, String>,
+    /// Ignores the video stream, if any. Music files often come with their cover art as a single
+    /// frame video stream, which we don't want to decode.
+    pub audio_only: bool,
+    /// Number of threads the video decoder may use, 0 letting ffmpeg pick based on the number of
+    /// cores. Frames are decoded in parallel, so each extra thread adds a frame of latency.
+    pub decoder_threads: usize,
+    /// Converts 8-bit videos into `BGRA` rather than `RGBA`.
+    pub bgra_output: bool,
+    /// Applies `LOW_LATENCY_FORMAT_OPTIONS` (unless they are set in `format_options`) and
+    /// decodes with `LOW_DELAY` and slice threading, see `TrackConfig::with_low_latency_mode`.
+    pub low_latency: bool,
+    /// Options of the audio resampler (libswresample), see `TrackConfig::swr_options`.
+    pub swr_options: HashMap<String, String>,
+    /// Shifts audio relative to video, in seconds, see `AudioState::audio_offset_pts`.
+    pub audio_delay: f64,
+    /// Seeks to the first frame once the session is open if the stream doesn't start at 0, see
+    /// `TrackConfig::seek_to_start`.
+    pub seek_to_start: bool,
+    /// The rows of the frames we output are padded to a multiple of this many bytes, see
+    /// `TrackConfig::with_output_stride_alignment`. 1 keeps them packed.
+    pub stride_alignment: usize,
+    /// Also outputs every frame at half its resolution, as a `ProcessOutput::VideoSubsampled`,
+    /// see `TrackConfig::with_subsampled_output`.
+    pub subsampled_output: bool,
+}
+
+/// Format options for `SessionOptions::low_latency`: no buffering and the shortest probe ffmpeg
+/// allows.
+const LOW_LATENCY_FORMAT_OPTIONS: [(&str, &str); 3] = [
+    ("fflags", "nobuffer"),
+    ("probesize", "32"),
+    ("analyzeduration", "0"),
+];
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            format_options: HashMap::new(),
+            audio_only: false,
+            decoder_threads: 1,
+            bgra_output: false,
+            low_latency: false,
+            swr_options: HashMap::new(),
+            audio_delay: 0.0,
+            seek_to_start: true,
+            stride_alignment: 1,
+            subsampled_output: false,
+        }
+    }
+}
+
+pub fn load_session(
+    source: &TrackSource,
+    options: &SessionOptions,
+) -> Result<MediaSession, MediaError> {
     ffmpeg::init()?;
-    let input_format_ctx = ffmpeg::format::input(source)?;
-    let video = if let Some(stream) = input_format_ctx.streams().best(ffmpeg::media::Type::Video) {
+    let low_latency_options = LOW_LATENCY_FORMAT_OPTIONS
+        .into_iter()
+        .filter(|(key, _)| options.low_latency && !options.format_options.contains_key(*key));
+    let dictionary: ffmpeg::Dictionary = options
+        .format_options
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .chain(low_latency_options)
+        .collect();
+    let input_format_ctx = ffmpeg::format::input_with_dictionary(&source.url(), dictionary)?;
+    let video_stream = input_format_ctx
+        .streams()
+        .best(ffmpeg::media::Type::Video)
+        .filter(|_| !options.audio_only);
+    let video = if let Some(stream) = video_stream {
         let stream_index = stream.index();
 
-        let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+        let mut context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+        let mut threading = ffmpeg::threading::Config::count(options.decoder_threads);
+        // Frame threading holds back a frame per thread, slice threading doesn't.
+        threading.kind = if options.low_latency {
+            ffmpeg::threading::Type::Slice
+        } else {
+            ffmpeg::threading::Type::Frame
+        };
+        context.set_threading(threading);
+        if options.low_latency {
+            context.set_flags(ffmpeg::codec::Flags::LOW_DELAY);
+        }
 
         let decoder = context.decoder().video()?;
         let width = decoder.width();
         let height = decoder.height();
+        let bit_depth = pixel_bit_depth(decoder.format());
+        let output_format = if bit_depth > 8 {
+            ffmpeg::format::Pixel::RGBA64LE
+        } else if options.bgra_output {
+            ffmpeg::format::Pixel::BGRA
+        } else {
+            ffmpeg::format::Pixel::RGBA
+        };
 
         let scaler = ffmpeg::software::scaling::Context::get(
             decoder.format(),
             width,
             height,
-            ffmpeg::format::Pixel::RGBA,
+            output_format,
             width,
             height,
             ffmpeg::software::scaling::Flags::BILINEAR,
         )?;
 
+        let (subsampled_width, subsampled_height) = ((width / 2).max(1), (height / 2).max(1));
+        let subsampler = if options.subsampled_output {
+            Some(ffmpeg::software::scaling::Context::get(
+                decoder.format(),
+                width,
+                height,
+                output_format,
+                subsampled_width,
+                subsampled_height,
+                ffmpeg::software::scaling::Flags::BILINEAR,
+            )?)
+        } else {
+            None
+        };
+
         let time_base = stream.time_base();
         let start_pts = stream.start_time();
+        let frame_rate = stream.avg_frame_rate();
+        let frame_duration = if frame_rate.numerator() > 0 && frame_rate.denominator() > 0 {
+            1i64.rescale(frame_rate.invert(), time_base).max(1)
+        } else {
+            1
+        };
 
         Some(VideoState {
             stream_index,
             decoder,
             scaler,
             decoded: ffmpeg::util::frame::Video::empty(),
+            filter_graph: None,
+            filtered: ffmpeg::util::frame::Video::empty(),
             width,
             height,
+            bit_depth,
+            output_format,
+            stride: aligned_stride(width, output_format, options.stride_alignment),
+            subsampler,
+            subsampled_stride: aligned_stride(
+                subsampled_width,
+                output_format,
+                options.stride_alignment,
+            ),
+            color_matrix: None,
             time_base,
             start_pts,
+            first_pts: 0,
+            decode_error_policy: DecodeErrorPolicy::default(),
+            consecutive_errors: 0,
+            skip_until_pts: None,
+            stop_after_pts: None,
+            last_packet_bitrate: None,
+            last_pts: None,
+            frame_duration,
         })
     } else {
         None
     };
 
-    Ok(MediaSession {
+    let audio = if let Some(stream) = input_format_ctx.streams().best(ffmpeg::media::Type::Audio) {
+        let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?;
+        let start_pts = match stream.start_time() {
+            ffmpeg::ffi::AV_NOPTS_VALUE => 0,
+            start_pts => start_pts,
+        };
+        let mut audio = AudioState {
+            stream_index: stream.index(),
+            decoder: context.decoder().audio()?,
+            resampler: None,
+            resampler_options: options.swr_options.clone(),
+            decoded: ffmpeg::util::frame::Audio::empty(),
+            time_base: stream.time_base(),
+            start_pts,
+            audio_offset_pts: 0,
+            last_rms: 0.0,
+        };
+        audio.audio_offset_pts = audio_delay_to_pts(&audio, options.audio_delay);
+        Some(audio)
+    } else {
+        None
+    };
+
+    // Subtitles are a nice to have, so we simply go without them if we can't decode them.
+    let subtitle = input_format_ctx
+        .streams()
+        .best(ffmpeg::media::Type::Subtitle)
+        .and_then(|stream| {
+            let context = ffmpeg::codec::context::Context::from_parameters(stream.parameters());
+            Some(SubtitleState {
+                stream_index: stream.index(),
+                decoder: context.ok()?.decoder().subtitle().ok()?,
+                time_base: stream.time_base(),
+            })
+        });
+
+    let streams = input_format_ctx
+        .streams()
+        .map(|stream| {
+            let parameters = stream.parameters();
+            StreamDescriptor {
+                index: stream.index(),
+                stream_type: parameters.medium().into(),
+                language: stream.metadata().get("language").map(str::to_string),
+                codec_name: parameters.id().name().to_string(),
+            }
+        })
+        .collect();
+
+    let attached_picture = attached_picture(&input_format_ctx);
+
+    let mut session = MediaSession {
         input_format_ctx,
         video: video,
-    })
+        audio,
+        subtitle,
+        streams,
+        pending_packets: VecDeque::new(),
+        attached_picture,
+    };
+
+    let first_pts = find_first_pts(&mut session)?;
+    if let Some(video) = &mut session.video {
+        video.first_pts = match first_pts {
+            Some(first_pts) => first_pts,
+            None if video.start_pts != ffmpeg::ffi::AV_NOPTS_VALUE => video.start_pts,
+            None => 0,
+        };
+    }
+
+    // If this fails, we simply start from wherever the demuxer is.
+    if options.seek_to_start {
+        match (&session.video, &session.audio) {
+            (Some(video), _) if video.start_pts > 0 => {
+                let first_pts = video.first_pts;
+                seek_pts(&mut session, first_pts, SeekMode::Precise).ok();
+            }
+            (None, Some(audio)) if audio.start_pts > 0 => {
+                seek_audio(&mut session, 0.0).ok();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(session)
+}
+
+/// How many packets we are willing to read to find the first frame before giving up.
+const MAX_FIRST_PTS_PACKETS: usize = 512;
+
+/// Decodes until we get the first video frame and returns its pts. The stream's declared start
+/// time is often not the pts of the first frame, and it is not even set for some streams.
+///
+/// We can't seek back afterwards since not every input is seekable, so the packets we read are
+/// stored in `pending_packets` and `read_packet` hands them out again. The decoder is flushed, so
+/// they are decoded from scratch.
+fn find_first_pts(session: &mut MediaSession) -> Result<Option<i64>, ffmpeg::Error> {
+    let Some(video) = &mut session.video else {
+        return Ok(None);
+    };
+
+    let mut first_pts = None;
+    while first_pts.is_none() && session.pending_packets.len() < MAX_FIRST_PTS_PACKETS {
+        let mut packet = ffmpeg::Packet::empty();
+        match packet.read(&mut session.input_format_ctx) {
+            Ok(_) => {}
+            Err(ffmpeg::Error::Eof) => break,
+            Err(e) => return Err(e),
+        }
+
+        if packet.stream() == video.stream_index {
+            video.decoder.send_packet(&packet)?;
+            while first_pts.is_none() && video.decoder.receive_frame(&mut video.decoded).is_ok() {
+                first_pts = video.decoded.pts().or(video.decoded.timestamp());
+            }
+        }
+        session.pending_packets.push_back(packet);
+    }
+    video.decoder.flush();
+
+    Ok(first_pts)
+}
+
+/// Returns the picture of the first stream marked as `ATTACHED_PIC`, as it is stored in the
+/// container. Demuxers read these when opening the input, so this doesn't touch the packets
+/// `read_packet` hands out.
+pub(crate) fn attached_picture(
+    input_format_ctx: &ffmpeg::format::context::Input,
+) -> Option<Vec<u8>> {
+    let stream = input_format_ctx.streams().find(|stream| {
+        stream
+            .disposition()
+            .contains(ffmpeg::format::stream::Disposition::ATTACHED_PIC)
+    })?;
+    // ffmpeg-next doesn't expose `attached_pic`, but it is a plain packet owned by the stream.
+    unsafe {
+        let packet = &(*stream.as_ptr()).attached_pic;
+        if packet.data.is_null() || packet.size <= 0 {
+            return None;
+        }
+        Some(std::slice::from_raw_parts(packet.data, packet.size as usize).to_vec())
+    }
+}
+
+/// Demuxers of inputs that never end on their own, so reaching their end means the connection
+/// dropped.
+const LIVE_FORMATS: [&str; 3] = ["rtsp", "rtp", "sdp"];
+
+/// Whether the session reads from a live stream (e.g. an RTSP camera) rather than a file.
+pub fn is_live_input(session: &MediaSession) -> bool {
+    LIVE_FORMATS.contains(&session.input_format_ctx.format().name())
+}
+
+/// Number of streams of the given type in the container.
+pub(crate) fn stream_count(
+    input_format_ctx: &ffmpeg::format::context::Input,
+    medium: ffmpeg::media::Type,
+) -> usize {
+    input_format_ctx
+        .streams()
+        .filter(|stream| stream.parameters().medium() == medium)
+        .count()
+}
+
+pub fn video_stream_count(session: &MediaSession) -> usize {
+    stream_count(&session.input_format_ctx, ffmpeg::media::Type::Video)
+}
+
+pub fn audio_stream_count(session: &MediaSession) -> usize {
+    stream_count(&session.input_format_ctx, ffmpeg::media::Type::Audio)
 }
 
 pub fn read_packet(session: &mut MediaSession) -> Result<Packet, ffmpeg::Error> {
+    if let Some(packet) = session.pending_packets.pop_front() {
+        return Ok(Packet::Packet(packet));
+    }
+
     let mut packet = ffmpeg::Packet::empty();
     match packet.read(&mut session.input_format_ctx) {
         Ok(_) => Ok(Packet::Packet(packet)),
@@ -131,32 +1159,1053 @@ pub fn read_packet(session: &mut MediaSession) -> Result<Packet, ffmpeg::Error>
     }
 }
 
+/// Byte offset the demuxer is at in the input, i.e. where the next packet will be read from. This
+/// is `None` for inputs that aren't read through ffmpeg's IO layer (e.g. some devices) or that
+/// can't tell, like some network streams.
+pub fn packet_position(session: &MediaSession) -> Option<u64> {
+    unsafe {
+        let pb = (*session.input_format_ctx.as_ptr()).pb;
+        if pb.is_null() {
+            return None;
+        }
+        // This is what `avio_tell` does, which is an inline function bindgen doesn't see. 1 is
+        // `SEEK_CUR`, and seeking 0 bytes from the current position doesn't move anything.
+        let position = ffmpeg::ffi::avio_seek(pb, 0, 1);
+        u64::try_from(position).ok()
+    }
+}
+
+/// Reads up to `n` packets in one go, to save a round trip through the worker loop per packet.
+/// The `bool` is whether the end of the input was reached, in which case there may be fewer than
+/// `n` packets. An error after the first packet just ends the batch early, so that the packets
+/// read before it aren't lost. Reading again will run into it anyway.
+pub fn read_n_packets(
+    session: &mut MediaSession,
+    n: usize,
+) -> Result<(Vec<ffmpeg::Packet>, bool), ffmpeg::Error> {
+    let mut packets = Vec::with_capacity(n);
+    while packets.len() < n {
+        match read_packet(session) {
+            Ok(Packet::Packet(packet)) => packets.push(packet),
+            Ok(Packet::Eof) => return Ok((packets, true)),
+            Err(e) if packets.is_empty() => return Err(e),
+            Err(_) => break,
+        }
+    }
+    Ok((packets, false))
+}
+
+/// The range of the media in seconds, on the timeline of the stream that drives playback (video,
+/// or audio when there is no video): from the first frame to the end of that stream. Returns
+/// `None` when neither the stream nor the container know the duration, e.g. for live streams.
+pub fn pts_range_in_seconds(session: &MediaSession) -> Option<(f64, f64)> {
+    let (stream_index, start_pts, time_base) = match (&session.video, &session.audio) {
+        (Some(video), _) => (video.stream_index, video.first_pts, video.time_base),
+        (None, Some(audio)) => (audio.stream_index, audio.start_pts, audio.time_base),
+        (None, None) => return None,
+    };
+    let stream = session.input_format_ctx.stream(stream_index)?;
+
+    let start_secs = start_pts as f64 * f64::from(time_base);
+    // Not every container has per-stream durations, in which case the container's one is close
+    // enough. Both are `AV_NOPTS_VALUE` (negative) when unknown.
+    let duration_secs = match stream.duration() {
+        duration if duration > 0 => duration as f64 * f64::from(time_base),
+        _ => match session.input_format_ctx.duration() {
+            duration if duration > 0 => duration as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE),
+            _ => return None,
+        },
+    };
+    Some((start_secs, start_secs + duration_secs))
+}
+
+/// Converts a position in seconds, relative to the first frame of the video stream, into a pts in
+/// the video stream's time base.
+pub fn seconds_to_pts(video: &VideoState, seconds: f64) -> i64 {
+    let microseconds = (seconds * 1_000_000.0) as i64;
+    video.first_pts + microseconds.rescale(ffmpeg::rescale::TIME_BASE, video.time_base)
+}
+
+/// Seeks the video stream to `pts`, which is expressed in the video stream's time base. Returns
+/// the pts decoding will actually resume from, which depends on `mode`.
+///
+/// For the keyframe modes, we have to know where the demuxer ended up, so we read packets until we
+/// get the first video packet and hand it over to the decoder right away. Its frame will come out
+/// of the decoder along with the next packet's. The audio and subtitle packets read before it are
+/// kept in `pending_packets`, so that `read_packet` still hands them out.
+pub fn seek_pts(session: &mut MediaSession, pts: i64, mode: SeekMode) -> Result<i64, MediaError> {
+    let Some(video) = &mut session.video else {
+        return Err(MediaError::NoVideoStream);
+    };
+
+    let (min_pts, max_pts, flags) = match mode {
+        SeekMode::Precise | SeekMode::KeyframeBackward => {
+            (i64::MIN, pts, ffmpeg::ffi::AVSEEK_FLAG_BACKWARD as i32)
+        }
+        SeekMode::KeyframeForward => (pts, i64::MAX, 0),
+    };
+
+    // `Input::seek` always seeks in `AV_TIME_BASE` and doesn't let us pass any flags, so we call
+    // into ffmpeg directly with the video stream's index so that `pts` can stay in its time base.
+    let ret = unsafe {
+        ffmpeg::ffi::avformat_seek_file(
+            session.input_format_ctx.as_mut_ptr(),
+            video.stream_index as i32,
+            min_pts,
+            pts,
+            max_pts,
+            flags,
+        )
+    };
+    if ret < 0 {
+        return Err(ffmpeg::Error::from(ret).into());
+    }
+    session.pending_packets.clear();
+    video.decoder.flush();
+    video.last_pts = None;
+    if let Some(audio) = &mut session.audio {
+        audio.decoder.flush();
+    }
+
+    if mode == SeekMode::Precise {
+        video.skip_until_pts = Some(pts);
+        return Ok(pts);
+    }
+    video.skip_until_pts = None;
+
+    loop {
+        let mut packet = ffmpeg::Packet::empty();
+        match packet.read(&mut session.input_format_ctx) {
+            Ok(_) if packet.stream() == video.stream_index => {
+                video.decoder.send_packet(&packet)?;
+                return Ok(packet.pts().or(packet.dts()).unwrap_or(pts));
+            }
+            Ok(_) => session.pending_packets.push_back(packet),
+            // There is nothing after the target, so the next read will hit the end of the stream
+            // again anyway.
+            Err(ffmpeg::Error::Eof) => return Ok(pts),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Skips ahead to the next video keyframe by reading packets without decoding them, which is much
+/// cheaper than decoding everything up to it. Packets of the other streams are skipped along. The
+/// keyframe is put back in front of the packets to read, and its pts (or dts) is returned. Returns
+/// `None` if the end of the input comes first.
+pub fn skip_to_next_keyframe(session: &mut MediaSession) -> Result<Option<i64>, MediaError> {
+    let Some(video_index) = session.video.as_ref().map(|video| video.stream_index) else {
+        return Err(MediaError::NoVideoStream);
+    };
+
+    loop {
+        let packet = match read_packet(session)? {
+            Packet::Packet(packet) => packet,
+            Packet::Eof => return Ok(None),
+        };
+        if packet.stream() != video_index || !packet.is_key() {
+            continue;
+        }
+
+        // The decoders may hold frames from before the packets we skipped.
+        if let Some(video) = &mut session.video {
+            video.decoder.flush();
+            video.skip_until_pts = None;
+            video.last_pts = None;
+        }
+        if let Some(audio) = &mut session.audio {
+            audio.decoder.flush();
+        }
+        let pts = packet.pts().or(packet.dts());
+        session.pending_packets.push_front(packet);
+        return Ok(pts);
+    }
+}
+
+/// Seeks a session without a video stream to `seconds`, relative to the audio stream's start.
+/// Returns the pts we seeked to, in the audio stream's time base. Audio packets are all keyframes
+/// for the codecs we care about, so there is no need for a `SeekMode`.
+pub fn seek_audio(session: &mut MediaSession, seconds: f64) -> Result<i64, MediaError> {
+    let Some(audio) = &mut session.audio else {
+        return Err(MediaError::NoAudioStream);
+    };
+
+    let microseconds = (seconds * 1_000_000.0) as i64;
+    let pts = audio.start_pts + microseconds.rescale(ffmpeg::rescale::TIME_BASE, audio.time_base);
+    let ret = unsafe {
+        ffmpeg::ffi::avformat_seek_file(
+            session.input_format_ctx.as_mut_ptr(),
+            audio.stream_index as i32,
+            i64::MIN,
+            pts,
+            pts,
+            ffmpeg::ffi::AVSEEK_FLAG_BACKWARD as i32,
+        )
+    };
+    if ret < 0 {
+        return Err(ffmpeg::Error::from(ret).into());
+    }
+    session.pending_packets.clear();
+    audio.decoder.flush();
+
+    Ok(pts)
+}
+
+/// Copies the part of `source` between `start_secs` and `end_secs` into a new file at
+/// `output_path`, without decoding or re-encoding anything. The output container is guessed from
+/// the extension of `output_path`. Video, audio and subtitle streams are kept, anything else is
+/// dropped.
+///
+/// Since we can only cut on packets, the segment actually starts at the keyframe before
+/// `start_secs`.
+pub fn remux_segment(
+    source: &TrackSource,
+    start_secs: f64,
+    end_secs: f64,
+    output_path: &str,
+) -> Result<(), MediaError> {
+    let mut session = load_media_session(source)?;
+    let Some(video) = &session.video else {
+        return Err(MediaError::NoVideoStream);
+    };
+    let video_stream_index = video.stream_index;
+    let video_time_base = video.time_base;
+    let start_pts = seconds_to_pts(video, start_secs);
+    let end_pts = seconds_to_pts(video, end_secs);
+
+    let mut output = ffmpeg::format::output(output_path)?;
+    // Input stream index -> (output stream index, input time base)
+    let mut stream_mapping = HashMap::new();
+    for stream in session.input_format_ctx.streams() {
+        let medium = stream.parameters().medium();
+        if !matches!(
+            medium,
+            ffmpeg::media::Type::Video | ffmpeg::media::Type::Audio | ffmpeg::media::Type::Subtitle
+        ) {
+            continue;
+        }
+        let mut out_stream = output.add_stream(ffmpeg::encoder::find(ffmpeg::codec::Id::None))?;
+        out_stream.set_parameters(stream.parameters());
+        // The codec tag of the input container doesn't necessarily mean anything in the output
+        // container, so we let the muxer pick one.
+        unsafe {
+            (*out_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        stream_mapping.insert(stream.index(), (out_stream.index(), stream.time_base()));
+    }
+
+    // We start in the middle of the stream, so we let the muxer shift everything back to 0.
+    let mut options = ffmpeg::Dictionary::new();
+    options.set("avoid_negative_ts", "make_zero");
+    output.write_header_with(options)?;
+
+    seek_pts(&mut session, start_pts, SeekMode::Precise)?;
+    loop {
+        let mut packet = match read_packet(&mut session)? {
+            Packet::Packet(packet) => packet,
+            Packet::Eof => break,
+        };
+        let Some(&(out_index, in_time_base)) = stream_mapping.get(&packet.stream()) else {
+            continue;
+        };
+
+        // We go by dts since, with B-frames, packets we still need can have a pts past the end.
+        if let Some(ts) = packet.dts().or(packet.pts())
+            && ts.rescale(in_time_base, video_time_base) > end_pts
+        {
+            if packet.stream() == video_stream_index {
+                break;
+            }
+            continue;
+        }
+
+        // The muxer may have changed the time bases of the output streams in `write_header`.
+        let out_time_base = output.stream(out_index).unwrap().time_base();
+        packet.rescale_ts(in_time_base, out_time_base);
+        packet.set_stream(out_index);
+        packet.set_position(-1);
+        packet.write_interleaved(&mut output)?;
+    }
+
+    output.write_trailer()?;
+    Ok(())
+}
+
+/// An output file that `VideoFrame`s are encoded into, see `open_mux_session`.
+pub struct MuxSession {
+    output_ctx: ffmpeg::format::context::Output,
+    encoder: ffmpeg::encoder::Video,
+    /// Converts our RGBA(64) frames into whatever the encoder wants, usually `YUV420P`.
+    scaler: ffmpeg::software::scaling::Context,
+    stream_index: usize,
+    /// One tick per frame.
+    time_base: ffmpeg::Rational,
+    next_pts: i64,
+}
+
+/// Creates `path` and gets it ready to receive `width` x `height` frames at `fps` frames per
+/// second, encoded with `codec`. The container is guessed from the extension of `path`, so e.g.
+/// `out.mp4` with `ffmpeg::codec::Id::H264` gives you a regular MP4.
+///
+/// Frames are written one after the other with `write_frame`, and the file is only complete once
+/// `close_mux_session` has been called.
+pub fn open_mux_session(
+    path: &str,
+    width: u32,
+    height: u32,
+    fps: ffmpeg::Rational,
+    codec: ffmpeg::codec::Id,
+) -> Result<MuxSession, MediaError> {
+    let mut output_ctx = ffmpeg::format::output(path)?;
+    let global_header = output_ctx
+        .format()
+        .flags()
+        .contains(ffmpeg::format::Flags::GLOBAL_HEADER);
+
+    let codec = ffmpeg::encoder::find(codec).ok_or(ffmpeg::Error::EncoderNotFound)?;
+    // Encoders that don't list their formats can usually take anything, so we go with the most
+    // common one.
+    let format = codec
+        .video()?
+        .formats()
+        .and_then(|mut formats| formats.next())
+        .unwrap_or(ffmpeg::format::Pixel::YUV420P);
+    let time_base = fps.invert();
+
+    let mut encoder = ffmpeg::codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    encoder.set_width(width);
+    encoder.set_height(height);
+    encoder.set_format(format);
+    encoder.set_time_base(time_base);
+    encoder.set_frame_rate(Some(fps));
+    if global_header {
+        encoder.set_flags(ffmpeg::codec::Flags::GLOBAL_HEADER);
+    }
+    let encoder = encoder.open()?;
+
+    let mut stream = output_ctx.add_stream(codec)?;
+    stream.set_parameters(&encoder);
+    stream.set_time_base(time_base);
+    let stream_index = stream.index();
+    output_ctx.write_header()?;
+
+    let scaler = ffmpeg::software::scaling::Context::get(
+        ffmpeg::format::Pixel::RGBA,
+        width,
+        height,
+        format,
+        width,
+        height,
+        ffmpeg::software::scaling::Flags::BILINEAR,
+    )?;
+
+    Ok(MuxSession {
+        output_ctx,
+        encoder,
+        scaler,
+        stream_index,
+        time_base,
+        next_pts: 0,
+    })
+}
+
+/// Encodes `frame` as the next frame of the output. Frames of any size and of any of the formats
+/// we decode into can be written, they are scaled to the size the session was opened with. Their
+/// pts is ignored: frames are spaced evenly, `1 / fps` apart.
+pub fn write_frame(session: &mut MuxSession, frame: &VideoFrame) -> Result<(), MediaError> {
+    let required = frame.stride as usize * frame.height as usize;
+    if frame.data.len() < required {
+        return Err(MediaError::BufferTooSmall {
+            required,
+            got: frame.data.len(),
+        });
+    }
+
+    let mut input = ffmpeg::util::frame::Video::empty();
+    input.set_width(frame.width);
+    input.set_height(frame.height);
+    input.set_format(frame.pixel_format);
+    // The scaler only reads from its input, so it can point right into the frame's data. The
+    // frame doesn't own it, so nothing is freed when it is dropped.
+    let ret = unsafe {
+        let input_ptr = input.as_mut_ptr();
+        ffmpeg::ffi::av_image_fill_arrays(
+            (*input_ptr).data.as_mut_ptr(),
+            (*input_ptr).linesize.as_mut_ptr(),
+            frame.data.as_ptr(),
+            frame.pixel_format.into(),
+            frame.width as i32,
+            frame.height as i32,
+            1,
+        )
+    };
+    if ret < 0 {
+        return Err(ffmpeg::Error::from(ret).into());
+    }
+    unsafe {
+        (*input.as_mut_ptr()).linesize[0] = frame.stride as i32;
+    }
+
+    let scaler_input = session.scaler.input();
+    if scaler_input.format != frame.pixel_format
+        || scaler_input.width != frame.width
+        || scaler_input.height != frame.height
+    {
+        let output = session.scaler.output();
+        let (format, width, height) = (output.format, output.width, output.height);
+        session.scaler.cached(
+            frame.pixel_format,
+            frame.width,
+            frame.height,
+            format,
+            width,
+            height,
+            ffmpeg::software::scaling::Flags::BILINEAR,
+        );
+    }
+
+    // The encoder may hold on to the frames it is given, so each one gets its own buffer.
+    let mut encoded = ffmpeg::util::frame::Video::empty();
+    session.scaler.run(&input, &mut encoded)?;
+    encoded.set_pts(Some(session.next_pts));
+    session.next_pts += 1;
+
+    session.encoder.send_frame(&encoded)?;
+    write_encoded_packets(session)
+}
+
+/// Flushes the encoder and finishes the file. Until this is called, the output is usually not
+/// playable, as most containers write their index at the end.
+pub fn close_mux_session(mut session: MuxSession) -> Result<(), MediaError> {
+    session.encoder.send_eof()?;
+    write_encoded_packets(&mut session)?;
+    session.output_ctx.write_trailer()?;
+    Ok(())
+}
+
+/// Writes every packet the encoder has ready to the output.
+fn write_encoded_packets(session: &mut MuxSession) -> Result<(), MediaError> {
+    // The muxer may have changed the time base of the stream in `write_header`.
+    let out_time_base = session
+        .output_ctx
+        .stream(session.stream_index)
+        .unwrap()
+        .time_base();
+    let mut packet = ffmpeg::Packet::empty();
+    while session.encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(session.stream_index);
+        packet.rescale_ts(session.time_base, out_time_base);
+        packet.write_interleaved(&mut session.output_ctx)?;
+    }
+    Ok(())
+}
+
+/// Decodes the last frame with a pts lower than `pts`, which is what playing backwards boils down
+/// to. Since the decoder can only go forwards, this seeks to the keyframe before `pts` and decodes
+/// everything up to it, so it is quite expensive. Returns `None` if there is no frame before `pts`.
+pub fn decode_frame_before(
+    session: &mut MediaSession,
+    pool: &FramePool,
+    pts: i64,
+) -> Result<Option<VideoFrame>, MediaError> {
+    seek_pts(session, pts - 1, SeekMode::KeyframeBackward)?;
+
+    let mut best_frame: Option<VideoFrame> = None;
+    loop {
+        let (outputs, end_of_stream) = match read_packet(session)? {
+            Packet::Packet(packet) => (process_packet(session, &packet, Some(pool))?, false),
+            // Whatever the decoder still holds from `pts` on is of no use to us.
+            Packet::Eof => (flush_until(session, pool, pts - 1)?, true),
+        };
+
+        let mut reached_pts = end_of_stream;
+        for output in outputs {
+            match output {
+                ProcessOutput::Video(frame) => {
+                    if frame.pts.is_some_and(|frame_pts| frame_pts < pts) {
+                        if let Some(old_frame) = best_frame.replace(frame) {
+                            pool.recycle(old_frame.data).ok();
+                        }
+                    } else {
+                        pool.recycle(frame.data).ok();
+                        reached_pts = true;
+                    }
+                }
+                // We don't play audio backwards, and we only ever see subtitles we already decoded
+                // on the way forwards.
+                ProcessOutput::VideoSubsampled(_)
+                | ProcessOutput::Audio(_)
+                | ProcessOutput::Subtitle(_) => {}
+            }
+        }
+
+        if reached_pts {
+            return Ok(best_frame);
+        }
+    }
+}
+
+/// How many packets `frame_at_pts` reads before giving up.
+pub const MAX_SEARCH_PACKETS: usize = 1024;
+
+/// Decodes the first frame at or after `pts_secs` (relative to the first frame), for tools that
+/// need a single frame without going through a track, e.g. thumbnails. The session is left
+/// wherever the frame was found. See `frame_at_pts_within` to change how far we look.
+pub fn frame_at_pts(
+    session: &mut MediaSession,
+    pool: &FramePool,
+    pts_secs: f64,
+) -> Result<VideoFrame, MediaError> {
+    frame_at_pts_within(session, pool, pts_secs, MAX_SEARCH_PACKETS)
+}
+
+/// Same as `frame_at_pts`, but gives up with `MediaError::FrameNotFound` after `max_packets`
+/// packets instead of `MAX_SEARCH_PACKETS`. Packets of every stream count, not only video ones.
+pub fn frame_at_pts_within(
+    session: &mut MediaSession,
+    pool: &FramePool,
+    pts_secs: f64,
+    max_packets: usize,
+) -> Result<VideoFrame, MediaError> {
+    let Some(video) = &session.video else {
+        return Err(MediaError::NoVideoStream);
+    };
+    let target_pts = seconds_to_pts(video, pts_secs);
+    // A precise seek makes the decoder drop the frames before `target_pts` without scaling them.
+    seek_pts(session, target_pts, SeekMode::Precise)?;
+
+    let mut frame = None;
+    for _ in 0..max_packets {
+        let (outputs, end_of_stream) = match read_packet(session)? {
+            Packet::Packet(packet) => (process_packet(session, &packet, Some(pool))?, false),
+            Packet::Eof => (flush(session, Some(pool))?, true),
+        };
+
+        for output in outputs {
+            match output {
+                ProcessOutput::Video(video_frame) if frame.is_none() => frame = Some(video_frame),
+                ProcessOutput::Video(video_frame) => {
+                    pool.recycle(video_frame.data).ok();
+                }
+                ProcessOutput::VideoSubsampled(_)
+                | ProcessOutput::Audio(_)
+                | ProcessOutput::Subtitle(_) => {}
+            }
+        }
+
+        if frame.is_some() || end_of_stream {
+            break;
+        }
+    }
+    frame.ok_or(MediaError::FrameNotFound)
+}
+
+/// Converts `frame` into a `VideoFrame` of the given size and format, backed by a buffer of
+/// `pool`. The scaler is re-created if `frame` doesn't match what it was set up for, which happens
+/// when a filter graph changes the frame size or when the frame is cropped.
+fn scale_frame(
+    scaler: &mut ffmpeg::software::scaling::Context,
+    frame: &mut ffmpeg::util::frame::Video,
+    stride: u32,
+    color_matrix: Option<ColorMatrix>,
+    pool: &FramePool,
+) -> Result<Option<VideoFrame>, ffmpeg::Error> {
+    let crop = crop_rect(frame);
+    if crop.is_some() {
+        // This only moves the data pointers and shrinks the frame down to its active area, so
+        // the borders are never read. It fails if the crop doesn't fit, in which case we convert
+        // the whole frame.
+        unsafe {
+            ffmpeg::ffi::av_frame_apply_cropping(
+                frame.as_mut_ptr(),
+                ffmpeg::ffi::AV_FRAME_CROP_UNALIGNED as i32,
+            );
+        }
+    }
+
+    fit_scaler(scaler, frame, color_matrix)?;
+    let output = scaler.output();
+    let (format, width, height) = (output.format, output.width, output.height);
+
+    let Ok(mut buffer) = pool.get() else {
+        return Ok(None);
+    };
+    let scaled = create_video_frame_from_buffer(width, height, stride, format, &mut buffer)
+        .and_then(|mut rgb_frame| scaler.run(frame, &mut rgb_frame));
+    if let Err(e) = scaled {
+        // The pool would be a buffer short otherwise.
+        pool.recycle(buffer).ok();
+        return Err(e);
+    }
+
+    Ok(Some(VideoFrame {
+        width,
+        height,
+        stride,
+        data: buffer,
+        pts: frame.pts(),
+        dts: Some(frame.packet().dts).filter(|&dts| dts != ffmpeg::ffi::AV_NOPTS_VALUE),
+        pixel_format: format,
+        sample_aspect_ratio: Some(frame.aspect_ratio())
+            .filter(|sar| sar.numerator() > 0 && sar.denominator() > 0),
+        hdr_metadata: hdr_metadata(frame),
+        crop,
+        metadata: VideoFrameMetadata::default(),
+    }))
+}
+
+/// Re-creates `scaler` for frames like `frame` if it was set up for something else, which happens
+/// when a filter graph changes the frame size or when the frame is cropped. The output stays the
+/// same. A new context starts over with the default color space details, so `color_matrix` is
+/// applied again.
+fn fit_scaler(
+    scaler: &mut ffmpeg::software::scaling::Context,
+    frame: &ffmpeg::util::frame::Video,
+    color_matrix: Option<ColorMatrix>,
+) -> Result<(), ffmpeg::Error> {
+    let input = scaler.input();
+    if input.format == frame.format()
+        && input.width == frame.width()
+        && input.height == frame.height()
+    {
+        return Ok(());
+    }
+
+    let output = scaler.output();
+    let (format, width, height) = (output.format, output.width, output.height);
+    scaler.cached(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        format,
+        width,
+        height,
+        ffmpeg::software::scaling::Flags::BILINEAR,
+    );
+    match color_matrix {
+        Some(matrix) => apply_color_matrix(scaler, matrix),
+        None => Ok(()),
+    }
+}
+
+/// Makes `scaler` convert YUV into RGB with `matrix`. Its other color space details (ranges,
+/// brightness, contrast and saturation) are kept as they are.
+fn apply_color_matrix(
+    scaler: &mut ffmpeg::software::scaling::Context,
+    matrix: ColorMatrix,
+) -> Result<(), ffmpeg::Error> {
+    unsafe {
+        let context = scaler.as_mut_ptr();
+        let mut inv_table = ptr::null_mut();
+        let mut table = ptr::null_mut();
+        let (mut src_range, mut dst_range) = (0, 0);
+        let (mut brightness, mut contrast, mut saturation) = (0, 0, 0);
+        let ret = ffmpeg::ffi::sws_getColorspaceDetails(
+            context,
+            &mut inv_table,
+            &mut src_range,
+            &mut table,
+            &mut dst_range,
+            &mut brightness,
+            &mut contrast,
+            &mut saturation,
+        );
+        if ret < 0 {
+            return Err(ffmpeg::Error::from(ret));
+        }
+        // `table` points into the context, which ffmpeg is fine with.
+        let ret = ffmpeg::ffi::sws_setColorspaceDetails(
+            context,
+            ffmpeg::ffi::sws_getCoefficients(matrix.sws_colorspace()),
+            src_range,
+            table,
+            dst_range,
+            brightness,
+            contrast,
+            saturation,
+        );
+        if ret < 0 {
+            return Err(ffmpeg::Error::from(ret));
+        }
+    }
+    Ok(())
+}
+
+/// Scales `source`, which `frame` was just converted from, down into the half resolution version of
+/// `frame`, see `SessionOptions::subsampled_output`. The pool's buffers are the size of full
+/// frames, so this one allocates its own.
+fn subsample_frame(
+    subsampler: &mut ffmpeg::software::scaling::Context,
+    source: &ffmpeg::util::frame::Video,
+    frame: &VideoFrame,
+    stride: u32,
+    color_matrix: Option<ColorMatrix>,
+) -> Result<VideoFrame, ffmpeg::Error> {
+    fit_scaler(subsampler, source, color_matrix)?;
+    let output = subsampler.output();
+    let (format, width, height) = (output.format, output.width, output.height);
+
+    let mut buffer = vec![0u8; stride as usize * height as usize];
+    let mut subsampled =
+        create_video_frame_from_buffer(width, height, stride, format, &mut buffer)?;
+    subsampler.run(source, &mut subsampled)?;
+
+    Ok(VideoFrame {
+        width,
+        height,
+        stride,
+        data: buffer,
+        ..*frame
+    })
+}
+
+/// Pulls every frame the decoder has ready, runs them through the filter graph if there is one,
+/// converts them into RGBA(64) and pushes them to `outputs`.
+fn receive_frames(
+    video: &mut VideoState,
+    pool: &FramePool,
+    outputs: &mut Vec<ProcessOutput>,
+) -> Result<(), ffmpeg::Error> {
+    while video.decoder.receive_frame(&mut video.decoded).is_ok() {
+        let pts_synthesized = video.decoded.pts().is_none();
+        if pts_synthesized {
+            let pts = Some(video.decoded.packet().dts)
+                .filter(|&dts| dts != ffmpeg::ffi::AV_NOPTS_VALUE)
+                .or(video.last_pts.map(|pts| pts + video.frame_duration));
+            video.decoded.set_pts(pts);
+        }
+        video.last_pts = video.decoded.pts().or(video.last_pts);
+
+        let metadata = VideoFrameMetadata {
+            bitrate_kbps: video.last_packet_bitrate.map(|bps| (bps / 1000.0) as f32),
+            pts_synthesized: pts_synthesized && video.decoded.pts().is_some(),
+        };
+        let with_metadata = move |mut frame: VideoFrame| {
+            frame.metadata = metadata;
+            frame
+        };
+
+        if let Some(target) = video.skip_until_pts {
+            match video.decoded.pts() {
+                Some(pts) if pts < target => continue,
+                _ => video.skip_until_pts = None,
+            }
+        }
+        if let Some(until) = video.stop_after_pts
+            && video.decoded.pts().is_some_and(|pts| pts > until)
+        {
+            continue;
+        }
+
+        let (stride, subsampled_stride) = (video.stride, video.subsampled_stride);
+        let color_matrix = video.color_matrix;
+        match &mut video.filter_graph {
+            Some(graph) => {
+                graph.get("in").unwrap().source().add(&video.decoded)?;
+                // Filters may hold frames back or turn a single frame into many, so we take
+                // whatever the graph has ready.
+                while graph
+                    .get("out")
+                    .unwrap()
+                    .sink()
+                    .frame(&mut video.filtered)
+                    .is_ok()
+                {
+                    let Some(frame) = scale_frame(
+                        &mut video.scaler,
+                        &mut video.filtered,
+                        stride,
+                        color_matrix,
+                        pool,
+                    )?
+                    else {
+                        continue;
+                    };
+                    let frame = with_metadata(frame);
+                    let subsampled = match &mut video.subsampler {
+                        Some(subsampler) => Some(subsample_frame(
+                            subsampler,
+                            &video.filtered,
+                            &frame,
+                            subsampled_stride,
+                            color_matrix,
+                        )?),
+                        None => None,
+                    };
+                    outputs.push(ProcessOutput::Video(frame));
+                    outputs.extend(subsampled.map(ProcessOutput::VideoSubsampled));
+                }
+            }
+            None => {
+                let Some(frame) = scale_frame(
+                    &mut video.scaler,
+                    &mut video.decoded,
+                    stride,
+                    color_matrix,
+                    pool,
+                )?
+                else {
+                    continue;
+                };
+                let frame = with_metadata(frame);
+                let subsampled = match &mut video.subsampler {
+                    Some(subsampler) => Some(subsample_frame(
+                        subsampler,
+                        &video.decoded,
+                        &frame,
+                        subsampled_stride,
+                        color_matrix,
+                    )?),
+                    None => None,
+                };
+                outputs.push(ProcessOutput::Video(frame));
+                outputs.extend(subsampled.map(ProcessOutput::VideoSubsampled));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds a filter graph out of an ffmpeg filtergraph description (e.g. `"vflip"` or
+/// `"scale=iw/2:ih/2,vflip"`) that decoded frames go through before they are converted. The output
+/// is always scaled back to the original video size, since that is what the frame pool is sized
+/// for. An empty description removes the filter graph.
+pub fn set_filter_graph(session: &mut MediaSession, description: &str) -> Result<(), MediaError> {
+    let Some(video) = &mut session.video else {
+        return Err(MediaError::NoVideoStream);
+    };
+
+    if description.trim().is_empty() {
+        video.filter_graph = None;
+        return Ok(());
+    }
+
+    let mut aspect_ratio = video.decoder.aspect_ratio();
+    if aspect_ratio.numerator() == 0 {
+        aspect_ratio = ffmpeg::Rational::new(1, 1);
+    }
+    let pixel_format: ffmpeg::ffi::AVPixelFormat = video.decoder.format().into();
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+        video.decoder.width(),
+        video.decoder.height(),
+        pixel_format as i32,
+        video.time_base,
+        aspect_ratio,
+    );
+
+    let mut graph = ffmpeg::filter::Graph::new();
+    let buffer = ffmpeg::filter::find("buffer").ok_or(ffmpeg::Error::FilterNotFound)?;
+    let buffer_sink = ffmpeg::filter::find("buffersink").ok_or(ffmpeg::Error::FilterNotFound)?;
+    graph.add(&buffer, "in", &args)?;
+    graph.add(&buffer_sink, "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse(description)?;
+    graph.validate()?;
+
+    video.filter_graph = Some(graph);
+    Ok(())
+}
+
+/// Converts the decoded frames from YUV into RGB with `matrix` from now on, rather than with
+/// whatever libswscale goes with. This only picks the weights of the conversion: the output is
+/// still gamma encoded (not linear), and HDR frames are not tone mapped. Frames that are already
+/// RGB are left alone.
+pub fn set_color_matrix(session: &mut MediaSession, matrix: ColorMatrix) -> Result<(), MediaError> {
+    let Some(video) = &mut session.video else {
+        return Err(MediaError::NoVideoStream);
+    };
+    apply_color_matrix(&mut video.scaler, matrix)?;
+    if let Some(subsampler) = &mut video.subsampler {
+        apply_color_matrix(subsampler, matrix)?;
+    }
+    video.color_matrix = Some(matrix);
+    Ok(())
+}
+
+/// Strips an ASS event (`ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`) down to
+/// its text, dropping the `{...}` override tags and turning `\N` into actual line breaks.
+fn ass_event_text(event: &str) -> String {
+    let text = event.splitn(9, ',').nth(8).unwrap_or(event);
+    let mut result = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_tag = true,
+            '}' => in_tag = false,
+            _ if !in_tag => result.push(c),
+            _ => {}
+        }
+    }
+    result.replace("\\N", "\n").replace("\\n", "\n")
+}
+
+/// Decodes a subtitle packet. Broken subtitles are not worth stopping playback for, so errors are
+/// simply ignored.
+fn decode_subtitle(
+    subtitle: &mut SubtitleState,
+    packet: &ffmpeg::Packet,
+    video_time_base: ffmpeg::Rational,
+) -> Option<SubtitleFrame> {
+    let mut decoded = ffmpeg::Subtitle::new();
+    if !subtitle.decoder.decode(packet, &mut decoded).ok()? {
+        return None;
+    }
+
+    let text = decoded
+        .rects()
+        .filter_map(|rect| match rect {
+            ffmpeg::subtitle::Rect::Text(text) => Some(text.get().to_string()),
+            ffmpeg::subtitle::Rect::Ass(ass) => Some(ass_event_text(ass.get())),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.is_empty() {
+        return None;
+    }
+
+    // The pts of a decoded subtitle is in `AV_TIME_BASE` units, and the display times are in
+    // milliseconds relative to it.
+    let time_base = ffmpeg::mathematics::rescale::TIME_BASE;
+    let pts = match decoded.pts() {
+        Some(pts) => pts,
+        None => packet.pts()?.rescale(subtitle.time_base, time_base),
+    };
+    let start = pts + decoded.start() as i64 * 1000;
+    let end = if decoded.end() > decoded.start() {
+        pts + decoded.end() as i64 * 1000
+    } else {
+        start + packet.duration().rescale(subtitle.time_base, time_base)
+    };
+
+    Some(SubtitleFrame {
+        start_pts: start.rescale(time_base, video_time_base),
+        end_pts: end.rescale(time_base, video_time_base),
+        text,
+    })
+}
+
+/// Root mean square of `samples`, 0 if there are none.
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples
+        .iter()
+        .map(|&sample| sample as f64 * sample as f64)
+        .sum();
+    (sum / samples.len() as f64).sqrt() as f32
+}
+
+/// Pulls every frame the audio decoder has ready, converts them into packed `f32` and pushes them
+/// to `outputs`.
+fn receive_audio_frames(
+    audio: &mut AudioState,
+    outputs: &mut Vec<ProcessOutput>,
+) -> Result<(), ffmpeg::Error> {
+    while audio.decoder.receive_frame(&mut audio.decoded).is_ok() {
+        // Some streams don't say which channel is which, but the resampler needs to know.
+        if audio.decoded.channel_layout().is_empty() {
+            let layout = ffmpeg::ChannelLayout::default(audio.decoded.channels() as i32);
+            audio.decoded.set_channel_layout(layout);
+        }
+
+        if audio.resampler.is_none() {
+            let options: ffmpeg::Dictionary = audio
+                .resampler_options
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+            audio.resampler = Some(ffmpeg::software::resampling::Context::get_with(
+                audio.decoded.format(),
+                audio.decoded.channel_layout(),
+                audio.decoded.rate(),
+                ffmpeg::format::Sample::F32(ffmpeg::format::sample::Type::Packed),
+                audio.decoded.channel_layout(),
+                audio.decoded.rate(),
+                options,
+            )?);
+        }
+        let resampler = audio.resampler.as_mut().unwrap();
+
+        let mut resampled = ffmpeg::util::frame::Audio::empty();
+        resampler.run(&audio.decoded, &mut resampled)?;
+
+        // The plane is padded, so we only take the part that holds samples.
+        let channels = resampled.channels();
+        let len = resampled.samples() * channels as usize * size_of::<f32>();
+        let samples: Vec<f32> = resampled.data(0)[..len]
+            .chunks_exact(size_of::<f32>())
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+        audio.last_rms = rms(&samples);
+
+        outputs.push(ProcessOutput::Audio(AudioFrame {
+            sample_rate: resampled.rate(),
+            channels,
+            samples,
+            pts: audio.decoded.pts().map(|pts| pts + audio.audio_offset_pts),
+            rms: audio.last_rms,
+        }));
+    }
+
+    Ok(())
+}
+
+/// Decodes `packet`, whichever stream it belongs to. `pool` is only needed if the session has a
+/// video stream.
 pub fn process_packet(
     session: &mut MediaSession,
     packet: &ffmpeg::Packet,
-    pool: &FramePool,
+    pool: Option<&FramePool>,
 ) -> Result<Vec<ProcessOutput>, ffmpeg::Error> {
     let mut outputs = Vec::new();
 
-    if let Some(video) = &mut session.video {
+    if let Some(audio) = &mut session.audio
+        && packet.stream() == audio.stream_index
+    {
+        // Unlike video, the odd broken audio packet is barely noticeable, so we don't bother
+        // going through the decode error policy and simply carry on.
+        audio
+            .decoder
+            .send_packet(packet)
+            .and_then(|_| receive_audio_frames(audio, &mut outputs))
+            .ok();
+        return Ok(outputs);
+    }
+
+    if let Some(subtitle) = &mut session.subtitle
+        && let Some(video) = &session.video
+        && packet.stream() == subtitle.stream_index
+    {
+        outputs.extend(
+            decode_subtitle(subtitle, packet, video.time_base).map(ProcessOutput::Subtitle),
+        );
+        return Ok(outputs);
+    }
+
+    if let Some(video) = &mut session.video
+        && let Some(pool) = pool
+    {
         if packet.stream() == video.stream_index {
-            video.decoder.send_packet(packet)?;
-
-            while video.decoder.receive_frame(&mut video.decoded).is_ok() {
-                if let Ok(mut buffer) = pool.get() {
-                    let mut rgb_frame = create_video_frame_from_buffer(
-                        video.width,
-                        video.height,
-                        ffmpeg::format::Pixel::RGBA,
-                        &mut buffer,
-                    );
-                    video.scaler.run(&video.decoded, &mut rgb_frame)?;
-                    outputs.push(ProcessOutput::Video(VideoFrame {
-                        width: video.width,
-                        height: video.height,
-                        data: buffer,
-                        pts: video.decoded.pts(),
-                    }));
+            video.last_packet_bitrate = get_packet_bitrate(packet, video.time_base);
+            let result = video
+                .decoder
+                .send_packet(packet)
+                .and_then(|_| receive_frames(video, pool, &mut outputs));
+            match result {
+                Ok(()) => video.consecutive_errors = 0,
+                Err(e) => {
+                    video.consecutive_errors += 1;
+                    if !video
+                        .decode_error_policy
+                        .tolerates(video.consecutive_errors)
+                    {
+                        return Err(e);
+                    }
                 }
             }
         }
@@ -167,31 +2216,244 @@ pub fn process_packet(
 
 pub fn flush(
     session: &mut MediaSession,
-    pool: &FramePool,
+    pool: Option<&FramePool>,
 ) -> Result<Vec<ProcessOutput>, ffmpeg::Error> {
     let mut outputs = Vec::new();
 
-    if let Some(video) = &mut session.video {
+    if let Some(audio) = &mut session.audio {
+        audio.decoder.send_eof().ok();
+        receive_audio_frames(audio, &mut outputs).ok();
+    }
+
+    if let Some(video) = &mut session.video
+        && let Some(pool) = pool
+    {
         video.decoder.send_eof().ok();
+        receive_frames(video, pool, &mut outputs)?;
+    }
 
-        while video.decoder.receive_frame(&mut video.decoded).is_ok() {
-            if let Ok(mut buffer) = pool.get() {
-                let mut rgb_frame = create_video_frame_from_buffer(
-                    video.width,
-                    video.height,
-                    ffmpeg::format::Pixel::RGBA,
-                    &mut buffer,
-                );
-                video.scaler.run(&video.decoded, &mut rgb_frame)?;
-                outputs.push(ProcessOutput::Video(VideoFrame {
-                    width: video.width,
-                    height: video.height,
-                    data: buffer,
-                    pts: video.decoded.pts(),
-                }));
-            }
+    Ok(outputs)
+}
+
+/// Same as `flush`, but video frames with a pts past `until_pts` (in the video stream's time base)
+/// are dropped before they are scaled, so they never take a buffer from `pool`. Since frames come
+/// out of the decoder in presentation order, everything up to `until_pts` is still emitted,
+/// B-frames included. Audio and subtitles are passed through untouched.
+pub fn flush_until(
+    session: &mut MediaSession,
+    pool: &FramePool,
+    until_pts: i64,
+) -> Result<Vec<ProcessOutput>, MediaError> {
+    if let Some(video) = &mut session.video {
+        video.stop_after_pts = Some(until_pts);
+    }
+    let outputs = flush(session, Some(pool));
+    if let Some(video) = &mut session.video {
+        video.stop_after_pts = None;
+    }
+    Ok(outputs?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame without padding, `stride` being whatever is left of `data` for every row.
+    fn video_frame(
+        width: u32,
+        height: u32,
+        pixel_format: ffmpeg::format::Pixel,
+        data: Vec<u8>,
+    ) -> VideoFrame {
+        VideoFrame {
+            width,
+            height,
+            stride: data.len() as u32 / height,
+            data,
+            pts: None,
+            dts: None,
+            pixel_format,
+            sample_aspect_ratio: None,
+            hdr_metadata: None,
+            crop: None,
+            metadata: VideoFrameMetadata::default(),
         }
     }
 
-    Ok(outputs)
+    #[test]
+    fn frames_from_buffers_need_room_for_every_byte() {
+        use ffmpeg::format::Pixel;
+
+        // 6 bytes a pixel, not 4.
+        let mut buffer = vec![0u8; 4 * 4 * 4];
+        assert!(matches!(
+            try_create_video_frame_from_buffer(4, 4, Pixel::RGB48LE, &mut buffer),
+            Err(MediaError::BufferTooSmall { required: 96, .. })
+        ));
+        let mut buffer = vec![0u8; 96];
+        let frame = try_create_video_frame_from_buffer(4, 4, Pixel::RGB48LE, &mut buffer).unwrap();
+        assert_eq!(frame.frame().stride(0), 24);
+
+        // A 4x4 luma plane and two 2x2 chroma planes.
+        let mut buffer = vec![0u8; 23];
+        assert!(try_create_video_frame_from_buffer(4, 4, Pixel::YUV420P, &mut buffer).is_err());
+        let mut buffer = vec![0u8; 24];
+        assert!(try_create_video_frame_from_buffer(4, 4, Pixel::YUV420P, &mut buffer).is_ok());
+    }
+
+    #[test]
+    fn frames_from_buffers_reject_what_ffmpeg_cant_lay_out() {
+        use ffmpeg::format::Pixel;
+
+        let mut buffer = vec![0u8; 1024];
+        for (width, height, format) in [
+            (4, 4, Pixel::None),
+            (0, 4, Pixel::RGBA),
+            (u32::MAX, 4, Pixel::RGBA),
+            (1 << 20, 1 << 20, Pixel::RGBA),
+        ] {
+            assert!(matches!(
+                try_create_video_frame_from_buffer(width, height, format, &mut buffer),
+                Err(MediaError::BufferTooSmall {
+                    required: usize::MAX,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[cfg(feature = "bevy_render")]
+    #[test]
+    fn frames_map_to_the_texture_format_of_their_pixels() {
+        use ffmpeg::format::Pixel;
+
+        for (pixel_format, bytes, texture_format) in [
+            (Pixel::RGBA, 4, TextureFormat::Rgba8UnormSrgb),
+            (Pixel::BGRA, 4, TextureFormat::Bgra8UnormSrgb),
+            (Pixel::RGBA64LE, 8, TextureFormat::Rgba16Unorm),
+        ] {
+            let frame = video_frame(2, 2, pixel_format, vec![0; 4 * bytes]);
+            assert_eq!(frame.texture_format(), texture_format);
+            let descriptor = frame.bevy_texture_descriptor();
+            assert_eq!(descriptor.format, texture_format);
+            assert_eq!((descriptor.size.width, descriptor.size.height), (2, 2));
+        }
+    }
+
+    #[test]
+    fn muxed_frames_can_be_read_back() {
+        ffmpeg::init().unwrap();
+        let path = std::env::temp_dir().join(format!("bevy-ffmpeg-mux-{}.mkv", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut session = open_mux_session(
+            path,
+            16,
+            16,
+            ffmpeg::Rational::new(25, 1),
+            ffmpeg::codec::Id::MPEG4,
+        )
+        .unwrap();
+        for i in 0..5 {
+            let frame = video_frame(
+                16,
+                16,
+                ffmpeg::format::Pixel::RGBA,
+                vec![i * 50; 16 * 16 * 4],
+            );
+            write_frame(&mut session, &frame).unwrap();
+        }
+        close_mux_session(session).unwrap();
+
+        let mut input = ffmpeg::format::input(path).unwrap();
+        let stream = input.streams().best(ffmpeg::media::Type::Video).unwrap();
+        let index = stream.index();
+        let decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+            .unwrap()
+            .decoder()
+            .video()
+            .unwrap();
+        assert_eq!(decoder.id(), ffmpeg::codec::Id::MPEG4);
+        assert_eq!((decoder.width(), decoder.height()), (16, 16));
+        let packets = input
+            .packets()
+            .filter(|(stream, _)| stream.index() == index)
+            .count();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(packets, 5);
+    }
+
+    #[test]
+    fn strides_are_rounded_up_to_the_alignment() {
+        use ffmpeg::format::Pixel;
+
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        assert_eq!(align_up(17, 16), 32);
+        assert_eq!(align_up(5, 1), 5);
+        assert_eq!(align_up(5, 0), 5);
+
+        assert_eq!(aligned_stride(3, Pixel::RGBA, 16), 16);
+        assert_eq!(aligned_stride(3, Pixel::RGBA64LE, 16), 32);
+        assert_eq!(aligned_stride(4, Pixel::RGBA, 0), 16);
+    }
+
+    #[test]
+    fn padding_is_left_out_of_rows_and_pixels() {
+        // Two pixels a row, padded with 4 bytes.
+        let data = [
+            &[1, 2, 3, 4, 5, 6, 7, 8][..],
+            &[0xee; 4],
+            &[9, 10, 11, 12, 13, 14, 15, 16],
+            &[0xee; 4],
+        ]
+        .concat();
+        let frame = video_frame(2, 2, ffmpeg::format::Pixel::RGBA, data);
+        assert_eq!(frame.stride, 12);
+
+        let rows: Vec<_> = frame.rows().collect();
+        assert_eq!(
+            rows,
+            [&[1, 2, 3, 4, 5, 6, 7, 8], &[9, 10, 11, 12, 13, 14, 15, 16]]
+        );
+        assert_eq!(frame.pixel_at(1, 1), Some([13, 14, 15, 16]));
+        assert_eq!(frame.pixel_at(2, 0), None);
+        assert_eq!(frame.pixel_at(0, 2), None);
+        assert_eq!(frame.copy_channel(0), Some(vec![1, 5, 9, 13]));
+        assert_eq!(frame.copy_channel(3), Some(vec![4, 8, 12, 16]));
+        assert_eq!(frame.copy_channel(4), None);
+    }
+
+    #[test]
+    fn pixels_come_out_as_rgba_whatever_the_format() {
+        use ffmpeg::format::Pixel;
+
+        let frame = video_frame(1, 1, Pixel::BGRA, vec![10, 20, 30, 40]);
+        assert_eq!(frame.pixel_at(0, 0), Some([30, 20, 10, 40]));
+        assert_eq!(frame.copy_channel(0), Some(vec![30]));
+        assert_eq!(frame.copy_channel(2), Some(vec![10]));
+        assert_eq!(frame.copy_channel(3), Some(vec![40]));
+
+        // Only the most significant byte of every component is kept.
+        let frame = video_frame(
+            1,
+            1,
+            Pixel::RGBA64LE,
+            vec![0x01, 0xa1, 0x02, 0xa2, 0x03, 0xa3, 0x04, 0xa4],
+        );
+        assert_eq!(frame.pixel_at(0, 0), Some([0xa1, 0xa2, 0xa3, 0xa4]));
+        assert_eq!(frame.copy_channel(1), Some(vec![0xa2]));
+    }
+
+    #[test]
+    fn ass_events_are_stripped_down_to_their_text() {
+        assert_eq!(
+            ass_event_text("0,0,Default,,0,0,0,,Hello {\\i1}world{\\i0}\\Nbye"),
+            "Hello world\nbye"
+        );
+        // Only the first eight commas separate fields.
+        assert_eq!(ass_event_text("0,0,Default,,0,0,0,,one, two"), "one, two");
+        assert_eq!(ass_event_text("{\\b1}bold"), "bold");
+    }
 }
@@ -0,0 +1,22 @@
+/// Counters describing how playback of a track went so far. These are purely for diagnostics and
+/// don't influence playback in any way.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "bevy_reflect", derive(bevy::reflect::Reflect))]
+pub struct PlaybackStats {
+    /// Number of decoded frames that were dropped by the worker because they had the same pts as
+    /// the frame sent right before them.
+    pub frames_deduped: u64,
+    /// Number of times the last frame was handed out again because the queue ran dry, see
+    /// `MediaEngine::set_duplicate_frame_on_stall`.
+    pub frames_duplicated: u64,
+    /// Number of frames that came without a pts, and got one made up from their dts or from the
+    /// frame rate, see `VideoFrameMetadata::pts_synthesized`.
+    pub synthesized_pts_count: u64,
+    /// Number of buffers given to `MediaEngine::reycle_video_frame_buffer` that the frame pool
+    /// rejected, see `FramePool::recycle`. Anything but 0 points at a buffer recycled twice, or at
+    /// one that doesn't come from the pool.
+    pub buffers_rejected: u64,
+    /// Memory taken by the buffers of the track's frame pool, see `FramePool::memory_usage_bytes`.
+    pub pool_memory_bytes: usize,
+}
@@ -1,33 +1,201 @@
+use super::audio_sink::AudioSink;
+use super::config::{DecodeErrorPolicy, FrameCallback, TrackSource};
+use super::error::MediaError;
 use super::frame_pool::FramePool;
+use super::probe::{TrackInfo, input_info};
 use super::session::{
-    MediaSession, Packet, ProcessOutput, VideoFrame, flush, load_media_session, process_packet,
-    read_packet,
+    AudioFrame, ColorMatrix, MediaSession, Packet, ProcessOutput, SeekMode, SessionOptions,
+    StreamDescriptor, SubtitleFrame, VideoFrame, audio_delay_to_pts, decode_frame_before,
+    decoder_frame_delay, flush, is_live_input, load_session, packet_position, process_packet,
+    pts_range_in_seconds, read_packet, seconds_to_pts, seek_audio, seek_pts, set_color_matrix,
+    set_filter_graph, skip_to_next_keyframe,
 };
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
 use ffmpeg_next as ffmpeg;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Number of frames that can be decoded ahead of the consumer.
+const DEFAULT_POOL_SIZE: usize = 10;
+
+/// How far ahead of real time we decode audio when there is no video. With video, the frame pool
+/// keeps us from running too far ahead, but nothing would stop us from decoding a whole music file
+/// into memory otherwise.
+const AUDIO_LEAD: Duration = Duration::from_secs(1);
 
 pub struct WorkerHandle {
     pub cmd_tx: Sender<WorkerCommand>,
     pub msg_rx: Receiver<WorkerMessage>,
+    alive: Arc<AtomicBool>,
+}
+
+impl WorkerHandle {
+    /// Whether the worker thread is still running. It stops if it panics, or once every command
+    /// sender is dropped.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Acquire)
+    }
+
+    /// Sends `cmd` to the worker thread. This fails with `MediaError::ChannelClosed` if the thread
+    /// is gone.
+    pub fn send_command(&self, cmd: WorkerCommand) -> Result<(), MediaError> {
+        self.cmd_tx.send(cmd).map_err(|_| MediaError::ChannelClosed)
+    }
 }
 
 pub enum WorkerCommand {
-    Load(String),
+    Load {
+        source: TrackSource,
+        /// Options passed to ffmpeg when opening the source.
+        options: HashMap<String, String>,
+    },
+    /// Same as `Load`, for HTTP(S) streams that need extra request headers, e.g. `Authorization` or
+    /// `Cookie`. The headers are passed to ffmpeg through its `http_headers` option.
+    LoadUrl {
+        url: String,
+        headers: HashMap<String, String>,
+    },
+    /// Re-opens the last loaded source from scratch. This is the way to recover a track after it
+    /// ended up in an error state.
+    Reload,
     Play,
     Pause,
-    Seek(f64),
+    /// Decodes the given number of frames without starting playback.
+    Prefetch(usize),
+    /// Pauses by itself once this many more video frames have been sent, and lets the engine know
+    /// with `WorkerMessage::PausedAfterFrames`.
+    PauseAfterFrames(u32),
+    /// Only decodes video keyframes, skipping every other video packet without looking into it.
+    /// Frames then only come at every keyframe, but cost next to nothing to get.
+    KeyframeOnly(bool),
+    Seek {
+        seconds: f64,
+        mode: SeekMode,
+    },
+    /// Jumps to the next video keyframe without decoding what is in between, see
+    /// `session::skip_to_next_keyframe`. A `WorkerMessage::Seeked` is sent once there.
+    SkipToKeyframe,
+    /// Drops whatever the video decoder holds, i.e. the frames it has buffered and the ones it
+    /// references, without moving in the input. Until the next keyframe, frames may then come out
+    /// broken, or not at all.
+    FlushDecoder,
+    /// Switches between playing forwards and backwards. Playing backwards is a lot more expensive
+    /// since every frame requires decoding from the keyframe before it.
+    SetDirection {
+        forward: bool,
+    },
+    /// Runs decoded frames through the given ffmpeg filtergraph description, e.g. `"vflip"`. An
+    /// empty description disables filtering.
+    SetFilterGraph(String),
+    /// Converts frames from YUV into RGB with the given matrix, see `session::set_color_matrix`.
+    SetColorMatrix(ColorMatrix),
+    /// Sets how decode errors are dealt with. This sticks across reloads.
+    SetDecodeErrorPolicy(DecodeErrorPolicy),
+    /// Sets how often `WorkerMessage::PositionUpdate` is sent.
+    SetPositionUpdateInterval(Duration),
+    /// How long the worker sleeps between two looks at its commands while it has nothing to
+    /// decode, see `TrackConfig::worker_idle_sleep_us`.
+    SetIdleSleep(Duration),
+    /// Caps the decode speed to the given number of frames per second. A non-positive value
+    /// removes the limit.
+    SetDecodeRate(f64),
+    /// Limits the memory taken by frames that are yet to be recycled, see
+    /// `TrackConfig::max_queue_memory_bytes`. This only applies from the next (re)load on.
+    SetMaxQueueMemory(Option<usize>),
+    /// Ignores the video stream, if any. This only applies from the next (re)load on.
+    SetAudioOnly(bool),
+    /// Sets how many threads the video decoder may use, see `SessionOptions::decoder_threads`.
+    /// This only applies from the next (re)load on.
+    SetDecoderThreads(usize),
+    /// Converts 8-bit videos into `BGRA` instead of `RGBA`. This only applies from the next
+    /// (re)load on.
+    SetBgraOutput(bool),
+    /// Tunes the next (re)load for live sources, see `SessionOptions::low_latency`.
+    SetLowLatency(bool),
+    /// Whether the next (re)load seeks to the first frame, see `SessionOptions::seek_to_start`.
+    SetSeekToStart(bool),
+    /// Pads the rows of the frames to a multiple of this many bytes, see
+    /// `SessionOptions::stride_alignment`. This only applies from the next (re)load on.
+    SetStrideAlignment(usize),
+    /// Also sends every frame at half its resolution, see `SessionOptions::subsampled_output`.
+    /// This only applies from the next (re)load on.
+    SetSubsampledOutput(bool),
+    /// Asks for a `WorkerMessage::CurrentPts` right away, rather than waiting for the next
+    /// position update.
+    GetCurrentPts,
+    /// How many times we try to reopen a live stream that ended, see
+    /// `TrackConfig::max_reconnect_attempts`.
+    SetMaxReconnectAttempts(Option<u32>),
+    /// Options of the audio resampler, see `SessionOptions::swr_options`. This only applies from
+    /// the next (re)load on.
+    SetSwrOptions(HashMap<String, String>),
+    /// Adds a sink every decoded chunk of audio is written to.
+    AddAudioSink(Arc<dyn AudioSink>),
+    /// Delays the audio by this many seconds (or plays it early, if negative) relative to the
+    /// video.
+    SetAudioDelay(f64),
+    /// Sets the callback every decoded frame goes through, see `TrackConfig::frame_callback`.
+    SetFrameCallback(Option<FrameCallback>),
+    /// Decodes into this pool from now on, see `MediaEngine::replace_frame_pool`. This is ignored
+    /// if the session has no video, and the next (re)load creates a pool of its own again.
+    ReplacePool(FramePool),
 }
 
 pub enum WorkerMessage {
+    /// Sent first whenever a source is opened.
+    TrackInfo(TrackInfo),
     Initialized {
         width: u32,
         height: u32,
+        bit_depth: u8,
         pool: FramePool,
         time_base: ffmpeg::Rational,
+        /// Pts of the first frame of the video. Frame timestamps are relative to it.
+        first_pts: i64,
+        streams: Vec<StreamDescriptor>,
+        attached_picture: Option<Vec<u8>>,
+    },
+    /// Sent right after `Initialized` if there is an audio stream, or on its own for audio only
+    /// media.
+    AudioInitialized {
+        sample_rate: u32,
+        channels: u16,
+        time_base: ffmpeg::Rational,
+        /// Audio timestamps are relative to it when there is no video.
         start_pts: i64,
     },
     VideoFrame(VideoFrame),
+    /// Half resolution version of the last `VideoFrame`, see `SessionOptions::subsampled_output`.
+    SubsampledVideoFrame(VideoFrame),
+    AudioFrame(AudioFrame),
+    SubtitleFrame(SubtitleFrame),
+    /// A seek went through. Every frame sent after this one comes from the new position.
+    Seeked,
+    /// Sent periodically, even while paused, with the pts of the last decoded frame (or where the
+    /// last seek landed).
+    PositionUpdate {
+        pts: i64,
+        /// Where the demuxer is in the input, in bytes, see `session::packet_position`.
+        byte_position: Option<u64>,
+    },
+    /// Same as `PositionUpdate`, in answer to `WorkerCommand::GetCurrentPts`. Nothing is sent
+    /// if nothing was decoded yet.
+    CurrentPts(i64),
+    /// A live stream ended unexpectedly and we will try to reopen it, for the `attempt`th time,
+    /// in `2^attempt` seconds. The track is re-initialized if that works.
+    Reconnecting {
+        attempt: u32,
+    },
+    /// A decoded frame was dropped because its pts was the same as the previous frame's.
+    FrameDeduped,
+    /// The worker paused by itself, see `WorkerCommand::PauseAfterFrames`.
+    PausedAfterFrames,
     EndOfStream,
+    /// Something is off, but not enough to stop playback.
+    Warning(String),
     Error(String),
 }
 
@@ -35,93 +203,641 @@ pub fn spawn_worker_thread() -> WorkerHandle {
     let (cmd_tx, cmd_rx) = crossbeam_channel::unbounded();
     let (msg_tx, msg_rx) = crossbeam_channel::unbounded();
 
+    // This starts as `true` so that the handle doesn't look dead before the thread gets to run.
+    let alive = Arc::new(AtomicBool::new(true));
+    let thread_alive = alive.clone();
+
     std::thread::spawn(move || {
-        worker_loop(cmd_rx, msg_tx);
+        thread_alive.store(true, Ordering::Release);
+        // We don't care about the panic payload, the engine only needs to know that we are gone.
+        std::panic::catch_unwind(AssertUnwindSafe(|| worker_loop(cmd_rx, msg_tx))).ok();
+        thread_alive.store(false, Ordering::Release);
     });
 
-    WorkerHandle { cmd_tx, msg_rx }
+    WorkerHandle {
+        cmd_tx,
+        msg_rx,
+        alive,
+    }
+}
+
+/// A handle without a thread behind it, so that tests can play the part of the worker through the
+/// other ends of its channels.
+#[cfg(test)]
+pub fn detached_worker() -> (WorkerHandle, Receiver<WorkerCommand>, Sender<WorkerMessage>) {
+    let (cmd_tx, cmd_rx) = crossbeam_channel::unbounded();
+    let (msg_tx, msg_rx) = crossbeam_channel::unbounded();
+    let handle = WorkerHandle {
+        cmd_tx,
+        msg_rx,
+        alive: Arc::new(AtomicBool::new(true)),
+    };
+    (handle, cmd_rx, msg_tx)
+}
+
+/// Formats `headers` the way ffmpeg's `http_headers` option expects them: one `Key: Value` line per
+/// header, each terminated by CRLF. A line break in a header would let it smuggle in headers of its
+/// own, so those are rejected, along with names that are empty or contain a colon.
+fn format_http_headers(headers: &HashMap<String, String>) -> Result<String, MediaError> {
+    let mut formatted = String::new();
+    for (key, value) in headers {
+        let has_line_break = |s: &str| s.contains(['\r', '\n']);
+        if key.is_empty() || key.contains(':') || has_line_break(key) || has_line_break(value) {
+            return Err(MediaError::InvalidHttpHeader(key.clone()));
+        }
+        formatted.push_str(&format!("{key}: {value}\r\n"));
+    }
+    Ok(formatted)
+}
+
+/// Loads the media session from `source` and lets the engine know about the outcome. On success,
+/// the session is returned alongside the frame pool its video frames will be decoded into.
+fn open_session(
+    source: &TrackSource,
+    options: &SessionOptions,
+    decode_error_policy: DecodeErrorPolicy,
+    max_queue_memory_bytes: Option<usize>,
+    msg_tx: &Sender<WorkerMessage>,
+) -> (Option<MediaSession>, Option<FramePool>) {
+    match try_open_session(
+        source,
+        options,
+        decode_error_policy,
+        max_queue_memory_bytes,
+        msg_tx,
+    ) {
+        Ok((s, frame_pool)) => (Some(s), frame_pool),
+        Err(e) => {
+            msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
+            (None, None)
+        }
+    }
+}
+
+/// Same as `open_session`, but failures are left to the caller.
+fn try_open_session(
+    source: &TrackSource,
+    options: &SessionOptions,
+    decode_error_policy: DecodeErrorPolicy,
+    max_queue_memory_bytes: Option<usize>,
+    msg_tx: &Sender<WorkerMessage>,
+) -> Result<(MediaSession, Option<FramePool>), MediaError> {
+    let mut s = load_session(source, options)?;
+    if let Some(video) = &mut s.video {
+        video.decode_error_policy = decode_error_policy;
+    }
+    if let Ok(mut info) = input_info(&s.input_format_ctx) {
+        info.time_range = pts_range_in_seconds(&s);
+        msg_tx.send(WorkerMessage::TrackInfo(info)).ok();
+    }
+    let mut frame_pool = None;
+    if let Some(video) = &s.video {
+        let frame_size = video.stride as usize * video.height as usize;
+        // Below this, we could end up waiting on buffers we hold ourselves: every frame the
+        // decoder held back, plus the one `decode_frame_before` keeps while decoding the next.
+        let min_buffers = decoder_frame_delay(video) + 2;
+        // Frame threading holds a frame per thread, so with many threads the default is too low.
+        let pool_size = DEFAULT_POOL_SIZE.max(min_buffers);
+        // The pool hands out a limited number of buffers and `FramePool::get` blocks once
+        // they are all in use, so limiting the number of buffers limits the memory.
+        let num_buffers = match max_queue_memory_bytes {
+            Some(bytes) => (bytes / frame_size).clamp(min_buffers, pool_size),
+            None => pool_size,
+        };
+        let pool = FramePool::new(num_buffers, frame_size);
+        let time_base = video.time_base;
+        let first_pts = video.first_pts;
+        msg_tx
+            .send(WorkerMessage::Initialized {
+                width: video.width,
+                height: video.height,
+                bit_depth: video.bit_depth,
+                pool: pool.clone(),
+                time_base,
+                first_pts,
+                streams: s.streams.clone(),
+                attached_picture: s.attached_picture.clone(),
+            })
+            .ok();
+        frame_pool = Some(pool);
+    };
+    if let Some(audio) = &s.audio {
+        msg_tx
+            .send(WorkerMessage::AudioInitialized {
+                sample_rate: audio.decoder.rate(),
+                channels: audio.decoder.channels(),
+                time_base: audio.time_base,
+                start_pts: audio.start_pts,
+            })
+            .ok();
+    }
+    Ok((s, frame_pool))
 }
 
 pub fn worker_loop(cmd_rx: Receiver<WorkerCommand>, msg_tx: Sender<WorkerMessage>) {
+    let mut source: Option<TrackSource> = None;
+    let mut session_options = SessionOptions::default();
     let mut session: Option<MediaSession> = None;
     let mut frame_pool: Option<FramePool> = None;
 
+    let mut decode_error_policy = DecodeErrorPolicy::Stop;
+    let mut max_queue_memory_bytes: Option<usize> = None;
+    let mut max_reconnect_attempts: Option<u32> = None;
+    // The attempt we are at and when to make it, while a live stream is being reconnected.
+    let mut reconnect: Option<(u32, Instant)> = None;
+    let mut audio_sinks: Vec<Arc<dyn AudioSink>> = Vec::new();
+    let mut frame_callback: Option<FrameCallback> = None;
+
     let mut playing = false;
+    let mut forward = true;
+    let mut prefetch_remaining: usize = 0;
+    // Whether video packets that aren't keyframes are skipped, see `KeyframeOnly`.
+    let mut keyframe_only = false;
+    // Video frames left to send before we pause by ourselves, see `PauseAfterFrames`.
+    let mut frames_remaining: Option<u32> = None;
+    let mut decode_interval: Option<Duration> = None;
+    let mut last_frame_at = Instant::now();
+    let mut last_sent_pts: Option<i64> = None;
+    let mut current_pts: Option<i64> = None;
+    let mut position_update_interval = Duration::from_millis(500);
+    let mut last_position_update = Instant::now();
+    let mut idle_sleep = Duration::from_micros(1000);
+    // When we started playing audio without video, along with the pts we started from.
+    let mut audio_clock: Option<(Instant, i64)> = None;
+    // A command that came in while we were waiting for something to do.
+    let mut pending_command: Option<WorkerCommand> = None;
 
     loop {
-        while let Ok(cmd) = cmd_rx.try_recv() {
+        let mut received_command = false;
+        loop {
+            let next = match pending_command.take() {
+                Some(cmd) => Ok(cmd),
+                None => cmd_rx.try_recv(),
+            };
+            let cmd = match next {
+                Ok(cmd) => {
+                    received_command = true;
+                    cmd
+                }
+                Err(TryRecvError::Empty) => break,
+                // The track was destroyed, so nobody is listening to us anymore.
+                Err(TryRecvError::Disconnected) => return,
+            };
+            // Whether to open `source` once the command is handled.
+            let mut load = false;
             match cmd {
-                WorkerCommand::Load(path) => match load_media_session(&path) {
-                    Ok(s) => {
-                        if let Some(video) = &s.video {
-                            let pool =
-                                FramePool::new(10, (video.width * video.height * 4) as usize);
-                            let time_base = video.time_base;
-                            let start_pts = video.start_pts;
-                            msg_tx
-                                .send(WorkerMessage::Initialized {
-                                    width: video.width,
-                                    height: video.height,
-                                    pool: pool.clone(),
-                                    time_base,
-                                    start_pts,
-                                })
-                                .ok();
-                            frame_pool = Some(pool);
-                        };
-                        session = Some(s);
+                WorkerCommand::Load {
+                    source: track_source,
+                    options,
+                } => {
+                    session_options.format_options = options;
+                    source = Some(track_source);
+                    load = true;
+                }
+                WorkerCommand::LoadUrl { url, headers } => match format_http_headers(&headers) {
+                    Ok(formatted) => {
+                        session_options.format_options.clear();
+                        if !headers.is_empty() {
+                            session_options
+                                .format_options
+                                .insert("http_headers".to_string(), formatted);
+                        }
+                        source = Some(TrackSource::from(url));
+                        load = true;
+                    }
+                    Err(e) => {
+                        msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
                     }
-                    Err(e) => msg_tx
-                        .send(WorkerMessage::Error(e.to_string()))
-                        .ok()
-                        .unwrap(),
                 },
+                WorkerCommand::Reload => load = true,
 
-                WorkerCommand::Play => playing = true,
-                WorkerCommand::Pause => playing = false,
-
-                // The most difficult one probably :D
-                WorkerCommand::Seek(val) => _ = val,
-            }
-        }
+                WorkerCommand::Play => {
+                    playing = true;
+                    prefetch_remaining = 0;
+                    audio_clock = None;
+                }
+                WorkerCommand::Pause => {
+                    playing = false;
+                    audio_clock = None;
+                }
+                WorkerCommand::Prefetch(num_frames) => prefetch_remaining = num_frames,
+                WorkerCommand::PauseAfterFrames(num_frames) => {
+                    frames_remaining = Some(num_frames);
+                }
+                WorkerCommand::KeyframeOnly(enabled) => keyframe_only = enabled,
 
-        if playing {
-            if let Some(s) = session.as_mut()
-                && let Some(pool) = &frame_pool
-            {
-                match read_packet(s) {
-                    Ok(Packet::Packet(packet)) => {
-                        if let Ok(outputs) = process_packet(s, &packet, &pool) {
-                            for output in outputs {
-                                match output {
-                                    ProcessOutput::Video(frame) => {
-                                        msg_tx.send(WorkerMessage::VideoFrame(frame)).ok();
-                                    }
-                                }
+                WorkerCommand::Seek { seconds, mode } => {
+                    if let Some(s) = session.as_mut() {
+                        let result = match s.video.as_ref().map(|v| seconds_to_pts(v, seconds)) {
+                            Some(pts) => seek_pts(s, pts, mode),
+                            None => seek_audio(s, seconds),
+                        };
+                        audio_clock = None;
+                        match result {
+                            Ok(landed_pts) => {
+                                last_sent_pts = None;
+                                current_pts = Some(landed_pts);
+                                msg_tx.send(WorkerMessage::Seeked).ok();
+                            }
+                            Err(e) => {
+                                msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
                             }
                         }
                     }
+                }
 
-                    Ok(Packet::Eof) => {
-                        if let Ok(outputs) = flush(s, &pool) {
-                            for output in outputs {
-                                match output {
-                                    ProcessOutput::Video(frame) => {
-                                        msg_tx.send(WorkerMessage::VideoFrame(frame)).ok();
-                                    }
-                                }
+                WorkerCommand::SkipToKeyframe => {
+                    if let Some(s) = session.as_mut() {
+                        audio_clock = None;
+                        match skip_to_next_keyframe(s) {
+                            Ok(Some(pts)) => {
+                                last_sent_pts = None;
+                                current_pts = Some(pts);
+                                msg_tx.send(WorkerMessage::Seeked).ok();
+                            }
+                            // The next read runs into the end of the stream too, and handles it.
+                            Ok(None) => {}
+                            Err(e) => {
+                                msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
                             }
                         }
+                    }
+                }
 
-                        msg_tx.send(WorkerMessage::EndOfStream).ok();
-                        playing = false;
+                WorkerCommand::FlushDecoder => {
+                    if let Some(video) = session.as_mut().and_then(|s| s.video.as_mut()) {
+                        video.decoder.flush();
+                    }
+                }
+
+                WorkerCommand::SetDirection { forward: f } => forward = f,
+
+                WorkerCommand::SetFilterGraph(description) => {
+                    if let Some(s) = session.as_mut()
+                        && let Err(e) = set_filter_graph(s, &description)
+                    {
+                        msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
+                    }
+                }
+
+                WorkerCommand::SetColorMatrix(matrix) => {
+                    if let Some(s) = session.as_mut()
+                        && let Err(e) = set_color_matrix(s, matrix)
+                    {
+                        msg_tx.send(WorkerMessage::Warning(e.to_string())).ok();
+                    }
+                }
+
+                WorkerCommand::SetDecodeErrorPolicy(policy) => {
+                    decode_error_policy = policy;
+                    if let Some(video) = session.as_mut().and_then(|s| s.video.as_mut()) {
+                        video.decode_error_policy = policy;
+                    }
+                }
+
+                WorkerCommand::SetPositionUpdateInterval(interval) => {
+                    position_update_interval = interval;
+                }
+                WorkerCommand::SetIdleSleep(duration) => idle_sleep = duration,
+
+                WorkerCommand::SetMaxQueueMemory(bytes) => max_queue_memory_bytes = bytes,
+                WorkerCommand::SetAudioOnly(enabled) => session_options.audio_only = enabled,
+                WorkerCommand::SetBgraOutput(enabled) => session_options.bgra_output = enabled,
+                WorkerCommand::SetLowLatency(enabled) => session_options.low_latency = enabled,
+                WorkerCommand::SetSeekToStart(enabled) => session_options.seek_to_start = enabled,
+                WorkerCommand::SetStrideAlignment(alignment) => {
+                    session_options.stride_alignment = alignment;
+                }
+                WorkerCommand::SetSubsampledOutput(enabled) => {
+                    session_options.subsampled_output = enabled;
+                }
+                WorkerCommand::SetSwrOptions(options) => session_options.swr_options = options,
+                WorkerCommand::SetDecoderThreads(threads) => {
+                    session_options.decoder_threads = threads;
+                }
+
+                WorkerCommand::SetDecodeRate(fps_limit) => {
+                    decode_interval =
+                        (fps_limit > 0.0).then(|| Duration::from_secs_f64(1.0 / fps_limit));
+                }
+
+                WorkerCommand::GetCurrentPts => {
+                    if let Some(pts) = current_pts {
+                        msg_tx.send(WorkerMessage::CurrentPts(pts)).ok();
                     }
+                }
+
+                WorkerCommand::SetMaxReconnectAttempts(attempts) => {
+                    max_reconnect_attempts = attempts;
+                }
+
+                WorkerCommand::AddAudioSink(sink) => audio_sinks.push(sink),
+
+                WorkerCommand::SetFrameCallback(callback) => frame_callback = callback,
 
+                WorkerCommand::ReplacePool(pool) => {
+                    if frame_pool.is_some() {
+                        frame_pool = Some(pool);
+                    }
+                }
+
+                WorkerCommand::SetAudioDelay(seconds) => {
+                    session_options.audio_delay = seconds;
+                    if let Some(audio) = session.as_mut().and_then(|s| s.audio.as_mut()) {
+                        audio.audio_offset_pts = audio_delay_to_pts(audio, seconds);
+                    }
+                }
+            }
+
+            if load && let Some(track_source) = &source {
+                // Nothing about the playback of the previous session carries over to the new one.
+                playing = false;
+                reconnect = None;
+                last_sent_pts = None;
+                current_pts = None;
+                audio_clock = None;
+                (session, frame_pool) = open_session(
+                    track_source,
+                    &session_options,
+                    decode_error_policy,
+                    max_queue_memory_bytes,
+                    &msg_tx,
+                );
+            }
+        }
+
+        if let Some((attempt, at)) = reconnect
+            && Instant::now() >= at
+            && let Some(track_source) = &source
+        {
+            reconnect = None;
+            match try_open_session(
+                track_source,
+                &session_options,
+                decode_error_policy,
+                max_queue_memory_bytes,
+                &msg_tx,
+            ) {
+                Ok((s, pool)) => {
+                    (session, frame_pool) = (Some(s), pool);
+                    last_sent_pts = None;
+                    current_pts = None;
+                    audio_clock = None;
+                }
+                Err(_) if attempt < max_reconnect_attempts.unwrap_or(0) => {
+                    reconnect = Some((attempt + 1, reconnect_deadline(attempt + 1)));
+                    msg_tx
+                        .send(WorkerMessage::Reconnecting {
+                            attempt: attempt + 1,
+                        })
+                        .ok();
+                }
+                Err(e) => {
+                    let error = format!("couldn't reconnect after {attempt} attempts: {e}");
+                    msg_tx.send(WorkerMessage::Error(error)).ok();
+                    playing = false;
+                }
+            }
+        }
+
+        if last_position_update.elapsed() >= position_update_interval {
+            if let Some(pts) = current_pts {
+                let byte_position = session.as_ref().and_then(packet_position);
+                msg_tx
+                    .send(WorkerMessage::PositionUpdate { pts, byte_position })
+                    .ok();
+            }
+            last_position_update = Instant::now();
+        }
+
+        if (playing || prefetch_remaining > 0) && session.is_some() {
+            if let Some(s) = session.as_mut() {
+                let pool = frame_pool.as_ref();
+                let result: Result<(Vec<ProcessOutput>, bool), MediaError> = if forward {
+                    match read_packet(s) {
+                        // Skipped without being decoded, see `KeyframeOnly`.
+                        Ok(Packet::Packet(packet))
+                            if keyframe_only
+                                && !packet.is_key()
+                                && s.video.as_ref().map(|v| v.stream_index)
+                                    == Some(packet.stream()) =>
+                        {
+                            Ok((Vec::new(), false))
+                        }
+                        Ok(Packet::Packet(packet)) => process_packet(s, &packet, pool)
+                            .map(|outputs| (outputs, false))
+                            .map_err(Into::into),
+                        Ok(Packet::Eof) => flush(s, pool)
+                            .map(|outputs| (outputs, true))
+                            .map_err(Into::into),
+                        Err(e) => Err(e.into()),
+                    }
+                } else {
+                    // Going backwards, we decode a single frame at a time: the one right before
+                    // the last frame we sent, or the position we seeked to if we didn't send any
+                    // since. Once there is nothing before it, we reached the start, which is the
+                    // end of the stream for us.
+                    match last_sent_pts
+                        .or(current_pts)
+                        .zip(pool)
+                        .map(|(pts, pool)| decode_frame_before(s, pool, pts))
+                    {
+                        Some(Ok(Some(frame))) => Ok((vec![ProcessOutput::Video(frame)], false)),
+                        Some(Ok(None)) | None => Ok((Vec::new(), true)),
+                        Some(Err(e)) => Err(e),
+                    }
+                };
+
+                let (outputs, end_of_stream) = match result {
+                    Ok(result) => result,
+                    // Decode errors the track's `DecodeErrorPolicy` tolerates are already dealt
+                    // with by the session, so whatever gets here stops playback.
                     Err(e) => {
                         msg_tx.send(WorkerMessage::Error(e.to_string())).ok();
+                        playing = false;
+                        prefetch_remaining = 0;
+                        continue;
+                    }
+                };
+
+                // Whether the last video frame was a duplicate, in which case we drop its half
+                // resolution version too.
+                let mut deduped = false;
+                for output in outputs {
+                    match output {
+                        ProcessOutput::Video(frame) => {
+                            // Some badly muxed files repeat the same pts over and over. There is no
+                            // point in showing the same frame twice, so we just give the buffer
+                            // back to the pool.
+                            deduped = frame.pts.is_some() && frame.pts == last_sent_pts;
+                            if deduped {
+                                if let Some(pool) = pool {
+                                    pool.recycle(frame.data).ok();
+                                }
+                                msg_tx.send(WorkerMessage::FrameDeduped).ok();
+                                continue;
+                            }
+                            // Frames come out of the decoder in presentation order, so this means
+                            // the stream itself is broken (or a live stream reset its timestamps).
+                            if forward
+                                && let (Some(pts), Some(last_pts)) = (frame.pts, last_sent_pts)
+                                && pts < last_pts
+                            {
+                                let warning =
+                                    format!("pts went backwards from {last_pts} to {pts}");
+                                msg_tx.send(WorkerMessage::Warning(warning)).ok();
+                            }
+                            last_sent_pts = frame.pts;
+                            current_pts = frame.pts.or(current_pts);
+                            if let Some(callback) = &frame_callback {
+                                callback.call(&frame);
+                            }
+                            msg_tx.send(WorkerMessage::VideoFrame(frame)).ok();
+
+                            // Whatever time we spent decoding this frame counts towards the
+                            // interval, so we only sleep for the remainder.
+                            if let Some(interval) = decode_interval {
+                                std::thread::sleep(
+                                    interval.saturating_sub(last_frame_at.elapsed()),
+                                );
+                            }
+                            last_frame_at = Instant::now();
+
+                            if !playing {
+                                prefetch_remaining = prefetch_remaining.saturating_sub(1);
+                            }
+                            if let Some(remaining) = &mut frames_remaining {
+                                *remaining = remaining.saturating_sub(1);
+                                if *remaining == 0 {
+                                    frames_remaining = None;
+                                    playing = false;
+                                    prefetch_remaining = 0;
+                                    audio_clock = None;
+                                    msg_tx.send(WorkerMessage::PausedAfterFrames).ok();
+                                }
+                            }
+                        }
+                        ProcessOutput::VideoSubsampled(_) if deduped => {}
+                        ProcessOutput::VideoSubsampled(frame) => {
+                            msg_tx.send(WorkerMessage::SubsampledVideoFrame(frame)).ok();
+                        }
+                        ProcessOutput::Audio(frame) => {
+                            for sink in &audio_sinks {
+                                sink.write(&frame.samples, frame.sample_rate, frame.channels);
+                            }
+                            let pts = frame.pts;
+                            msg_tx.send(WorkerMessage::AudioFrame(frame)).ok();
+
+                            if s.video.is_none()
+                                && let Some(pts) = pts
+                                && let Some(audio) = &s.audio
+                            {
+                                current_pts = Some(pts);
+                                let (started_at, start_pts) =
+                                    *audio_clock.get_or_insert((Instant::now(), pts));
+                                let position =
+                                    (pts - start_pts) as f64 * f64::from(audio.time_base);
+                                let ahead = Duration::from_secs_f64(position.max(0.0))
+                                    .saturating_sub(started_at.elapsed());
+                                if ahead > AUDIO_LEAD {
+                                    std::thread::sleep(ahead - AUDIO_LEAD);
+                                }
+
+                                if !playing {
+                                    prefetch_remaining = prefetch_remaining.saturating_sub(1);
+                                }
+                            }
+                        }
+                        ProcessOutput::Subtitle(subtitle) => {
+                            msg_tx.send(WorkerMessage::SubtitleFrame(subtitle)).ok();
+                        }
                     }
                 }
+
+                if end_of_stream
+                    && forward
+                    && max_reconnect_attempts.is_some_and(|attempts| attempts > 0)
+                    && is_live_input(s)
+                {
+                    // Live streams don't end, so the connection dropped. We keep `playing` as is
+                    // so that playback picks up where it was once we are reconnected.
+                    reconnect = Some((1, reconnect_deadline(1)));
+                    msg_tx.send(WorkerMessage::Reconnecting { attempt: 1 }).ok();
+                } else if end_of_stream {
+                    msg_tx.send(WorkerMessage::EndOfStream).ok();
+                    playing = false;
+                    prefetch_remaining = 0;
+                }
+            }
+            if reconnect.is_some() {
+                (session, frame_pool) = (None, None);
+            }
+        } else if !received_command {
+            // Otherwise we would spin on the command channel and keep a core busy for nothing.
+            // While reconnecting there is nothing to do before the next attempt, so we wait for it.
+            let timeout = match reconnect {
+                Some((_, at)) => at.saturating_duration_since(Instant::now()),
+                None => idle_sleep,
+            };
+            match cmd_rx.recv_timeout(timeout) {
+                Ok(cmd) => pending_command = Some(cmd),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
             }
         }
     }
 }
+
+/// When to make the `attempt`th attempt at reconnecting, backing off exponentially.
+fn reconnect_deadline(attempt: u32) -> Instant {
+    Instant::now() + Duration::from_secs(2u64.saturating_pow(attempt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_headers_are_crlf_terminated_lines() {
+        let headers = HashMap::from([("Authorization".to_string(), "Bearer abc".to_string())]);
+        assert_eq!(
+            format_http_headers(&headers).unwrap(),
+            "Authorization: Bearer abc\r\n"
+        );
+    }
+
+    #[test]
+    fn every_http_header_gets_a_line() {
+        let headers = HashMap::from([
+            ("Cookie".to_string(), "a=1; b=2".to_string()),
+            ("User-Agent".to_string(), "bevy-ffmpeg".to_string()),
+        ]);
+        let formatted = format_http_headers(&headers).unwrap();
+
+        // `HashMap` doesn't keep the order the headers were inserted in, and neither does ffmpeg
+        // care about it.
+        let mut lines: Vec<&str> = formatted.split_inclusive("\r\n").collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            ["Cookie: a=1; b=2\r\n", "User-Agent: bevy-ffmpeg\r\n"]
+        );
+    }
+
+    #[test]
+    fn no_http_headers_format_to_nothing() {
+        assert_eq!(format_http_headers(&HashMap::new()).unwrap(), "");
+    }
+
+    #[test]
+    fn http_headers_cant_smuggle_other_headers() {
+        let header = |key: &str, value: &str| HashMap::from([(key.to_string(), value.to_string())]);
+
+        assert!(format_http_headers(&header("Cookie", "a=1\r\nX-Admin: 1")).is_err());
+        assert!(format_http_headers(&header("Cookie", "a=1\nX-Admin: 1")).is_err());
+        assert!(format_http_headers(&header("X-Admin: 1\r\nCookie", "a=1")).is_err());
+        assert!(format_http_headers(&header("X-Admin", "")).is_ok());
+        assert!(format_http_headers(&header("X-Admin: 1", "a=1")).is_err());
+        assert!(format_http_headers(&header("", "a=1")).is_err());
+    }
+}